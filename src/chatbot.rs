@@ -1,32 +1,170 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::time::Instant;
 
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::conversation::{TranscriptFormatter, Turn};
+use crate::memory::summary::SummaryMemory;
+use crate::memory::LongTermMemory;
+use crate::middleware::{After, Before, ConversationContext, Flow, Middleware};
 use crate::model_traits::{CompletionModel, Responder};
+use crate::response::{keys, Response};
+use crate::session::store::SessionStore;
+use crate::structured::StructuredOutput;
+use crate::style::StyleHandle;
+use crate::template::{current_date, PromptTemplate, TemplateRegistry};
+use crate::tokens::{truncate_to_tokens, ApproxTokenCounter, TokenCounter};
+
+const DEFAULT_CONTEXT_SIZE: usize = 2049;
+const DEFAULT_MAX_TOKENS: usize = 256;
+
+/// The template used when none is given. It lays the prompt out as the prefix,
+/// style instructions, pinned turns, conversation summary, recalled memories,
+/// history, the new input, the suffix and finally the reply cue.
+pub const DEFAULT_TEMPLATE: &str = "{prefix}{#if style}{style}
+{/if}{pinned}{#if summary}Summary of the conversation so far:
+{summary}
+{/if}{#if memories}Relevant memories from earlier conversations:
+{memories}
+{/if}{history}{input}
+{suffix}{reply_cue}";
+
+/// Variables the chatbot sets itself when rendering its template.
+pub const BUILTIN_VARIABLES: [&str; 10] = [
+    "prefix",
+    "style",
+    "suffix",
+    "pinned",
+    "summary",
+    "memories",
+    "history",
+    "input",
+    "date",
+    "reply_cue",
+];
+
+/// How old turns are removed once the history no longer fits the token budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TruncationStrategy {
+    /// Drop the oldest turns.
+    DropOldest,
+    /// Shorten the oldest turns to at most `max_turn_tokens` tokens first, and
+    /// only drop them if that isn't enough.
+    CompressOldest { max_turn_tokens: usize },
+}
 
-type ChatBotProcessor = dyn Fn(&str) -> Result<String, Box<dyn Error>>;
+/// Everything needed to resume a conversation with a `Chatbot`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatbotState {
+    pub conversation: Vec<Turn>,
+    pub pinned: Vec<Turn>,
+    pub summary: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    pub conversation_limit: Option<usize>,
+    pub context_size: Option<usize>,
+    pub max_tokens: Option<usize>,
+    pub model_settings: Option<serde_json::Value>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// `None` keeps whatever styles the chatbot was built with.
+    pub styles: Option<Vec<String>>,
+}
 
 pub struct ChatbotBuilder<T: CompletionModel> {
     model: T,
-    conversation_limit: usize,
+    conversation_limit: Option<usize>,
+    context_size: Option<usize>,
+    max_tokens: Option<usize>,
+    truncation: TruncationStrategy,
+    token_counter: Box<dyn TokenCounter>,
+    pinned: Vec<Turn>,
+    summary_memory: Option<SummaryMemory>,
+    long_term_memory: Option<Box<dyn LongTermMemory>>,
     prefix: Option<String>,
     suffix: Option<String>,
-    preprocessors: Vec<Box<ChatBotProcessor>>,
-    postprocessors: Vec<Box<ChatBotProcessor>>,
+    template: Option<PromptTemplate>,
+    templates: TemplateRegistry,
+    variables: HashMap<String, String>,
+    styles: StyleHandle,
+    formatter: TranscriptFormatter,
+    middleware: Vec<Box<dyn Middleware>>,
 }
 
 impl<T: CompletionModel> ChatbotBuilder<T> {
     pub fn new(model: T) -> Self {
         Self {
             model,
-            conversation_limit: 10,
+            conversation_limit: None,
+            context_size: None,
+            max_tokens: None,
+            truncation: TruncationStrategy::DropOldest,
+            token_counter: Box::new(ApproxTokenCounter),
+            pinned: Vec::new(),
+            summary_memory: None,
+            long_term_memory: None,
             prefix: None,
             suffix: None,
-            preprocessors: Vec::new(),
-            postprocessors: Vec::new(),
+            template: None,
+            templates: TemplateRegistry::new(),
+            variables: HashMap::new(),
+            styles: StyleHandle::new(),
+            formatter: TranscriptFormatter::default(),
+            middleware: Vec::new(),
         }
     }
 
+    /// The maximum number of turns kept in the conversation history, on top of
+    /// the token budget. A limit of 0 disables memory entirely.
     pub fn conversation_limit(mut self, limit: usize) -> Self {
-        self.conversation_limit = limit;
+        self.conversation_limit = Some(limit);
+        self
+    }
+
+    /// The model's context window. Defaults to the model's own value.
+    pub fn context_size(mut self, tokens: usize) -> Self {
+        self.context_size = Some(tokens);
+        self
+    }
+
+    /// Tokens reserved for the response. Defaults to the model's own value.
+    pub fn max_tokens(mut self, tokens: usize) -> Self {
+        self.max_tokens = Some(tokens);
+        self
+    }
+
+    pub fn truncation(mut self, strategy: TruncationStrategy) -> Self {
+        self.truncation = strategy;
+        self
+    }
+
+    pub fn token_counter<C: TokenCounter + 'static>(mut self, counter: C) -> Self {
+        self.token_counter = Box::new(counter);
+        self
+    }
+
+    /// Adds a turn that is always kept in the prompt, ahead of the history.
+    pub fn pin(mut self, turn: Turn) -> Self {
+        self.pinned.push(turn);
+        self
+    }
+
+    /// Pins system instructions to the top of every prompt.
+    pub fn system(self, instructions: &str) -> Self {
+        self.pin(Turn::system(instructions))
+    }
+
+    /// Summarizes turns evicted from the history instead of dropping them.
+    pub fn summary_memory(mut self, memory: SummaryMemory) -> Self {
+        self.summary_memory = Some(memory);
+        self
+    }
+
+    /// Recalls relevant past exchanges into the prompt and remembers new ones.
+    pub fn long_term_memory<M: LongTermMemory + 'static>(mut self, memory: M) -> Self {
+        self.long_term_memory = Some(Box::new(memory));
         self
     }
 
@@ -40,36 +178,173 @@ impl<T: CompletionModel> ChatbotBuilder<T> {
         self
     }
 
-    pub fn add_preprocessor(mut self, preprocessor: &'static ChatBotProcessor) -> Self {
-        self.preprocessors.push(Box::new(preprocessor));
+    /// The prompt template. Defaults to `DEFAULT_TEMPLATE`.
+    pub fn template(mut self, template: PromptTemplate) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Templates available to `{>name}` includes.
+    pub fn template_registry(mut self, templates: TemplateRegistry) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Sets a template variable. Names in `BUILTIN_VARIABLES` are rejected by `build`.
+    pub fn variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Shares style instructions with a `StyleManager`.
+    pub fn styles(mut self, styles: StyleHandle) -> Self {
+        self.styles = styles;
         self
     }
 
-    pub fn add_postprocessor(mut self, postprocessor: &'static ChatBotProcessor) -> Self {
-        self.postprocessors.push(Box::new(postprocessor));
+    pub fn formatter(mut self, formatter: TranscriptFormatter) -> Self {
+        self.formatter = formatter;
         self
     }
 
-    pub fn build(self) -> Chatbot<T> {
-        Chatbot {
+    /// Appends a stage to the middleware pipeline.
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Appends a stage that runs before the model call.
+    pub fn before<F>(self, stage: F) -> Self
+    where
+        F: FnMut(&ConversationContext, String) -> Result<Flow, Box<dyn Error>> + Send + 'static,
+    {
+        self.middleware(Before(stage))
+    }
+
+    /// Appends a stage that runs on the model's response.
+    pub fn after<F>(self, stage: F) -> Self
+    where
+        F: FnMut(&ConversationContext, &str, Response) -> Result<Response, Box<dyn Error>>
+            + Send
+            + 'static,
+    {
+        self.middleware(After(stage))
+    }
+
+    /// Appends a stage that rewrites the input.
+    pub fn add_preprocessor<F>(self, mut preprocessor: F) -> Self
+    where
+        F: FnMut(&str) -> Result<String, Box<dyn Error>> + Send + 'static,
+    {
+        self.before(move |_, input| Ok(Flow::Continue(preprocessor(&input)?)))
+    }
+
+    /// Appends a stage that rewrites the response.
+    pub fn add_postprocessor<F>(self, mut postprocessor: F) -> Self
+    where
+        F: FnMut(&str) -> Result<String, Box<dyn Error>> + Send + 'static,
+    {
+        self.after(move |_, _, output| {
+            Ok(Response {
+                text: postprocessor(&output.text)?,
+                ..output
+            })
+        })
+    }
+
+    /// Builds the chatbot, failing if the template uses variables that are never set
+    /// or a variable would replace a builtin one.
+    pub fn build(self) -> Result<Chatbot<T>, Box<dyn Error>> {
+        let template = match self.template {
+            Some(template) => template,
+            None => PromptTemplate::parse("default", DEFAULT_TEMPLATE)?,
+        };
+        for name in self.variables.keys() {
+            validate_variable_name(name)?;
+        }
+        validate_template(&template, &self.templates, &self.variables)?;
+
+        let context_size = self
+            .context_size
+            .or_else(|| self.model.context_size())
+            .unwrap_or(DEFAULT_CONTEXT_SIZE);
+        let max_tokens = self
+            .max_tokens
+            .or_else(|| self.model.max_tokens())
+            .unwrap_or(DEFAULT_MAX_TOKENS);
+
+        Ok(Chatbot {
             model: self.model,
             conversation: Vec::new(),
             conversation_limit: self.conversation_limit,
+            context_size,
+            max_tokens,
+            truncation: self.truncation,
+            token_counter: self.token_counter,
+            pinned: self.pinned,
+            summary_memory: self.summary_memory,
+            long_term_memory: self.long_term_memory,
+            recalled: Vec::new(),
             prefix: self.prefix,
             suffix: self.suffix,
-            preprocessors: self.preprocessors,
-            postprocessors: self.postprocessors,
-        }
+            template,
+            templates: self.templates,
+            variables: self.variables,
+            styles: self.styles,
+            formatter: self.formatter,
+            middleware: self.middleware,
+            session: None,
+        })
     }
 }
+
+fn validate_template(
+    template: &PromptTemplate,
+    templates: &TemplateRegistry,
+    variables: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let defined: HashSet<String> = BUILTIN_VARIABLES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(variables.keys().cloned())
+        .collect();
+
+    template.validate(templates, &defined)
+}
+
+/// Fails for the names of variables the chatbot sets itself.
+fn validate_variable_name(name: &str) -> Result<(), Box<dyn Error>> {
+    match BUILTIN_VARIABLES.contains(&name) {
+        true => Err(format!(
+            "`{}` is set by the chatbot and can't be used as a variable",
+            name
+        )
+        .into()),
+        false => Ok(()),
+    }
+}
+
 pub struct Chatbot<T: CompletionModel> {
     model: T,
-    conversation: Vec<String>,
-    conversation_limit: usize,
+    conversation: Vec<Turn>,
+    conversation_limit: Option<usize>,
+    context_size: usize,
+    max_tokens: usize,
+    truncation: TruncationStrategy,
+    token_counter: Box<dyn TokenCounter>,
+    pinned: Vec<Turn>,
+    summary_memory: Option<SummaryMemory>,
+    long_term_memory: Option<Box<dyn LongTermMemory>>,
+    recalled: Vec<String>,
     prefix: Option<String>,
     suffix: Option<String>,
-    preprocessors: Vec<Box<ChatBotProcessor>>,
-    postprocessors: Vec<Box<ChatBotProcessor>>,
+    template: PromptTemplate,
+    templates: TemplateRegistry,
+    variables: HashMap<String, String>,
+    styles: StyleHandle,
+    formatter: TranscriptFormatter,
+    middleware: Vec<Box<dyn Middleware>>,
+    session: Option<(String, Box<dyn SessionStore>)>,
 }
 
 impl<T: CompletionModel> Chatbot<T> {
@@ -77,8 +352,292 @@ impl<T: CompletionModel> Chatbot<T> {
         ChatbotBuilder::new(model)
     }
 
-    fn build_conversation_prompt(&self) -> String {
-        self.conversation.join("\n")
+    pub fn conversation(&self) -> &[Turn] {
+        &self.conversation
+    }
+
+    pub fn clear_conversation(&mut self) {
+        self.conversation.clear();
+        if let Some(memory) = self.summary_memory.as_mut() {
+            memory.clear();
+        }
+    }
+
+    pub fn pinned(&self) -> &[Turn] {
+        &self.pinned
+    }
+
+    pub fn pin(&mut self, turn: Turn) {
+        self.pinned.push(turn);
+    }
+
+    pub fn clear_pinned(&mut self) {
+        self.pinned.clear();
+    }
+
+    pub fn summary(&self) -> Option<&str> {
+        self.summary_memory
+            .as_ref()
+            .and_then(|memory| memory.summary())
+    }
+
+    /// Tokens available for pinned turns, summary, memories, history and the new
+    /// input: the context size less the response allowance and the rest of the
+    /// rendered template.
+    pub fn token_budget(&self) -> usize {
+        let empty = self.render_template(&[], &[], None, None, None);
+        let fixed = empty.map(|prompt| self.count(&prompt)).unwrap_or_default();
+
+        self.prompt_limit().saturating_sub(fixed)
+    }
+
+    fn prompt_limit(&self) -> usize {
+        self.context_size.saturating_sub(self.max_tokens)
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.token_counter.count(text)
+    }
+
+    fn count_turn(&self, turn: &Turn) -> usize {
+        // Each rendered turn is followed by a newline.
+        self.count(&self.formatter.render_turn(turn)) + 1
+    }
+
+    fn count_turns(&self, turns: &[Turn]) -> usize {
+        turns.iter().map(|turn| self.count_turn(turn)).sum()
+    }
+
+    /// Tokens left for history once everything else in the prompt for `input` is rendered.
+    fn history_allowance(&self, input: &Turn) -> Result<usize, Box<dyn Error>> {
+        let overhead = self.count(&self.render_prompt(&[], input)?);
+        let limit = self.prompt_limit();
+
+        match overhead > limit {
+            true => Err(format!(
+                "Input needs {} tokens but only {} are available in the context window",
+                overhead, limit
+            )
+            .into()),
+            false => Ok(limit - overhead),
+        }
+    }
+
+    /// Removes or compresses the oldest turns until the prompt for `input` fits
+    /// within the token budget.
+    fn fit_history(&mut self, input: &Turn) -> Result<(), Box<dyn Error>> {
+        if self.summary_memory.is_some() {
+            self.summarize_history(input)?;
+        }
+
+        let available = self.history_allowance(input)?;
+
+        if let TruncationStrategy::CompressOldest { max_turn_tokens } = self.truncation {
+            let mut index = 0;
+            while self.count_turns(&self.conversation) > available
+                && index < self.conversation.len()
+            {
+                let turn = &self.conversation[index];
+                let compressed =
+                    truncate_to_tokens(&*self.token_counter, &turn.content, max_turn_tokens);
+                self.conversation[index].content = compressed;
+                index += 1;
+            }
+        }
+
+        while self.count_turns(&self.conversation) > available {
+            self.conversation.remove(0);
+        }
+
+        Ok(())
+    }
+
+    /// The most recent turns of `history` that fit in the prompt for `input`.
+    fn fitted<'a>(&self, history: &'a [Turn], input: &Turn) -> Result<&'a [Turn], Box<dyn Error>> {
+        let available = self.history_allowance(input)?;
+
+        let mut start = 0;
+        while self.count_turns(&history[start..]) > available {
+            start += 1;
+        }
+        Ok(&history[start..])
+    }
+
+    /// Once the history passes the summary memory's trigger ratio, evicts the
+    /// oldest turns down to half that and folds them into the running summary.
+    fn summarize_history(&mut self, input: &Turn) -> Result<(), Box<dyn Error>> {
+        let ratio = match &self.summary_memory {
+            Some(memory) => memory.trigger_ratio(),
+            None => return Ok(()),
+        };
+
+        let available = self.history_allowance(input)?;
+        let trigger = (available as f32 * ratio) as usize;
+
+        if self.count_turns(&self.conversation) <= trigger {
+            return Ok(());
+        }
+
+        let mut count = 0;
+        while count < self.conversation.len()
+            && self.count_turns(&self.conversation[count..]) > trigger / 2
+        {
+            count += 1;
+        }
+
+        self.evict_oldest(count)
+    }
+
+    /// Removes the oldest `count` turns, first folding them into the summary memory
+    /// if there is one. Nothing is removed if summarizing fails.
+    fn evict_oldest(&mut self, count: usize) -> Result<(), Box<dyn Error>> {
+        if let Some(memory) = self.summary_memory.as_mut() {
+            let lines = self.formatter.render(&self.conversation[..count]);
+            memory.summarize(&lines)?;
+        }
+
+        self.conversation.drain(..count);
+        Ok(())
+    }
+
+    fn render_turns(&self, turns: &[Turn]) -> String {
+        turns
+            .iter()
+            .map(|turn| format!("{}\n", self.formatter.render_turn(turn)))
+            .collect()
+    }
+
+    /// Renders the template. Pinned turns, history and input are each followed
+    /// by a newline; missing pieces render as empty.
+    fn render_template(
+        &self,
+        pinned: &[Turn],
+        history: &[Turn],
+        input: Option<&Turn>,
+        summary: Option<&str>,
+        memories: Option<&[String]>,
+    ) -> Result<String, Box<dyn Error>> {
+        let memories = memories
+            .unwrap_or_default()
+            .iter()
+            .map(|memory| format!("- {}", memory.replace('\n', " / ")))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut variables: HashMap<String, String> = HashMap::from([
+            (
+                "prefix".to_string(),
+                self.prefix.clone().unwrap_or_default(),
+            ),
+            ("style".to_string(), self.styles.instructions()),
+            (
+                "suffix".to_string(),
+                self.suffix.clone().unwrap_or_default(),
+            ),
+            ("pinned".to_string(), self.render_turns(pinned)),
+            (
+                "summary".to_string(),
+                summary.unwrap_or_default().to_string(),
+            ),
+            ("memories".to_string(), memories),
+            ("history".to_string(), self.render_turns(history)),
+            (
+                "input".to_string(),
+                input
+                    .map(|turn| self.formatter.render_turn(turn))
+                    .unwrap_or_default(),
+            ),
+            ("date".to_string(), current_date()),
+            ("reply_cue".to_string(), self.formatter.reply_cue()),
+        ]);
+        variables.extend(self.variables.clone());
+
+        self.template.render(&variables, &self.templates)
+    }
+
+    fn render_prompt(&self, history: &[Turn], input: &Turn) -> Result<String, Box<dyn Error>> {
+        self.render_template(
+            &self.pinned,
+            history,
+            Some(input),
+            self.summary(),
+            Some(&self.recalled),
+        )
+    }
+
+    fn build_prompt(&self, input: &Turn) -> Result<String, Box<dyn Error>> {
+        self.render_prompt(&self.conversation, input)
+    }
+
+    pub fn state(&self) -> ChatbotState {
+        ChatbotState {
+            conversation: self.conversation.clone(),
+            pinned: self.pinned.clone(),
+            summary: self.summary().map(String::from),
+            prefix: self.prefix.clone(),
+            suffix: self.suffix.clone(),
+            conversation_limit: self.conversation_limit,
+            context_size: Some(self.context_size),
+            max_tokens: Some(self.max_tokens),
+            model_settings: self.model.settings(),
+            variables: self.variables.clone(),
+            styles: Some(self.styles.styles()),
+        }
+    }
+
+    pub fn restore(&mut self, state: ChatbotState) -> Result<(), Box<dyn Error>> {
+        for name in state.variables.keys() {
+            validate_variable_name(name)?;
+        }
+        if let Some(settings) = state.model_settings {
+            self.model.apply_settings(settings)?;
+        }
+
+        self.conversation = state.conversation;
+        self.pinned = state.pinned;
+        self.prefix = state.prefix;
+        self.suffix = state.suffix;
+        self.conversation_limit = state.conversation_limit;
+        self.context_size = state.context_size.unwrap_or(self.context_size);
+        self.max_tokens = state.max_tokens.unwrap_or(self.max_tokens);
+        self.variables.extend(state.variables);
+        if let Some(styles) = state.styles {
+            self.styles.set(styles);
+        }
+
+        if let Some(memory) = self.summary_memory.as_mut() {
+            memory.set_summary(state.summary);
+        }
+
+        Ok(())
+    }
+
+    /// Attaches the chatbot to session `id` in `store`, restoring its state if the
+    /// session exists. The state is saved after every response from then on.
+    ///
+    /// Returns whether an existing session was restored.
+    pub fn resume<S: SessionStore + 'static>(
+        &mut self,
+        id: &str,
+        store: S,
+    ) -> Result<bool, Box<dyn Error>> {
+        let restored = match store.load(id)? {
+            Some(state) => {
+                self.restore(state)?;
+                true
+            }
+            None => false,
+        };
+
+        self.session = Some((id.to_string(), Box::new(store)));
+        Ok(restored)
+    }
+
+    pub fn save_session(&self) -> Result<(), Box<dyn Error>> {
+        match &self.session {
+            Some((id, store)) => store.save(id, &self.state()),
+            None => Err("Chatbot is not attached to a session".into()),
+        }
     }
 
     pub fn set_prefix(&mut self, prefix: &str) {
@@ -88,48 +647,230 @@ impl<T: CompletionModel> Chatbot<T> {
     pub fn set_suffix(&mut self, suffix: &str) {
         self.suffix = Some(suffix.to_string());
     }
+
+    /// The style instructions rendered into the prompt. Clones share the same styles.
+    pub fn styles(&self) -> &StyleHandle {
+        &self.styles
+    }
+
+    pub fn variables(&self) -> &HashMap<String, String> {
+        &self.variables
+    }
+
+    /// Sets a template variable, failing for names in `BUILTIN_VARIABLES`.
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        validate_variable_name(name)?;
+        self.variables.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Replaces the template, failing if it uses variables that aren't set.
+    pub fn set_template(&mut self, template: PromptTemplate) -> Result<(), Box<dyn Error>> {
+        validate_template(&template, &self.templates, &self.variables)?;
+        self.template = template;
+        Ok(())
+    }
+
+    /// Responds to `input` and parses the reply as `O`. Replies that don't parse are
+    /// answered with the parse error, in the same conversation, until one does or
+    /// `output`'s retries run out.
+    ///
+    /// The model is asked directly, without middleware, with the attempts so far
+    /// on a scratch copy of the history. Only `input` and the reply that parsed are
+    /// recorded in the history, long-term memory and session; the format
+    /// instructions and failed attempts are dropped once the asking is over.
+    pub fn respond_as<O: DeserializeOwned>(
+        &mut self,
+        input: &str,
+        output: &StructuredOutput,
+    ) -> Result<O, Box<dyn Error>> {
+        let input = Turn::user(input.trim());
+        self.recalled = match &self.long_term_memory {
+            Some(memory) => self.recall(memory.as_ref(), &input.content)?,
+            None => Vec::new(),
+        };
+
+        let mut attempts = Vec::new();
+        let value = output.ask(&input.content, |request| {
+            let request = Turn::user(request.trim());
+            let history: Vec<Turn> = self.conversation.iter().chain(&attempts).cloned().collect();
+            let prompt = self.render_prompt(self.fitted(&history, &request)?, &request)?;
+
+            let reply = self
+                .model
+                .complete_with_stop(&prompt, &self.formatter.stop_sequences())?
+                .trim()
+                .to_string();
+            attempts.push(request);
+            attempts.push(Turn::assistant(&reply));
+            Ok(reply)
+        })?;
+
+        let reply = attempts.pop().expect("a reply was parsed");
+        if let Err(err) = self.record(input, reply) {
+            eprintln!("Unable to save session: {}", err);
+        }
+        Ok(value)
+    }
 }
 
-impl<T: CompletionModel> Responder for Chatbot<T> {
-    fn respond(&mut self, prompt: &str) -> Result<String, Box<dyn Error>> {
-        let prompt = match self.conversation.len() {
-            0 => prompt.trim().to_string(),
-            _ => format!("{}\n{}", self.build_conversation_prompt(), prompt),
+impl<T: CompletionModel> Chatbot<T> {
+    /// Runs the `before` middleware stages over `input`, rewriting it in place, and
+    /// returns the first direct reply if a stage gives one.
+    fn run_before(&mut self, input: &mut String) -> Result<Option<String>, Box<dyn Error>> {
+        let context = ConversationContext {
+            history: &self.conversation,
+            pinned: &self.pinned,
+            summary: self
+                .summary_memory
+                .as_ref()
+                .and_then(|memory| memory.summary()),
+            variables: &self.variables,
         };
 
-        let prompt = match &self.prefix {
-            Some(prefix) => format!("{}{}", prefix, prompt),
-            None => prompt,
+        for stage in self.middleware.iter_mut() {
+            match stage.before(&context, input.clone())? {
+                Flow::Continue(next) => *input = next,
+                Flow::Reply(reply) => return Ok(Some(reply)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs the `after` middleware stages over the model's response.
+    fn run_after(&mut self, input: &str, output: Response) -> Result<Response, Box<dyn Error>> {
+        let context = ConversationContext {
+            history: &self.conversation,
+            pinned: &self.pinned,
+            summary: self
+                .summary_memory
+                .as_ref()
+                .and_then(|memory| memory.summary()),
+            variables: &self.variables,
         };
 
-        let prompt = match &self.suffix {
-            Some(suffix) => format!("{}{}", prompt, suffix),
-            None => prompt,
+        self.middleware
+            .iter_mut()
+            .try_fold(output, |output, stage| stage.after(&context, input, output))
+    }
+
+    /// Memories relevant to `query`, leaving out exchanges still in the history.
+    fn recall(
+        &self,
+        memory: &dyn LongTermMemory,
+        query: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let live: HashSet<String> = self
+            .conversation
+            .windows(2)
+            .map(|exchange| self.formatter.render(exchange))
+            .collect();
+
+        let mut recalled = memory.recall(query)?;
+        recalled.retain(|memory| !live.contains(memory));
+        Ok(recalled)
+    }
+
+    /// Adds an exchange to the history and long-term memory, and saves the session.
+    /// Fails only if the session couldn't be saved, and keeps the exchange then.
+    fn record(&mut self, input: Turn, response: Turn) -> Result<(), Box<dyn Error>> {
+        if let Some(memory) = self.long_term_memory.as_mut() {
+            let exchange = self.formatter.render(&[input.clone(), response.clone()]);
+            if let Err(err) = memory.remember(&exchange) {
+                eprintln!("Unable to remember exchange: {}", err);
+            }
+        }
+
+        self.push_exchange(input, response)
+    }
+
+    /// Adds an exchange to the history and saves the session. Fails only if the
+    /// session couldn't be saved, and keeps the exchange then.
+    fn push_exchange(&mut self, input: Turn, response: Turn) -> Result<(), Box<dyn Error>> {
+        self.conversation.push(input);
+        self.conversation.push(response);
+
+        if let Some(limit) = self.conversation_limit {
+            if self.conversation.len() > limit {
+                // The response is already made, so keep the turns and try again
+                // next time rather than fail it.
+                if let Err(err) = self.evict_oldest(self.conversation.len() - limit) {
+                    eprintln!("Unable to summarize old turns: {}", err);
+                }
+            }
+        }
+
+        if self.session.is_some() {
+            self.save_session()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: CompletionModel> Responder for Chatbot<T> {
+    /// Runs the middleware pipeline around the model call. The history records
+    /// the input as rewritten by `before` stages and the model's response as it
+    /// was before `after` stages ran. If the session can't be saved afterwards, the
+    /// response is still returned, with the error as its `warning` metadata.
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>> {
+        let mut input = input.trim().to_string();
+        let reply = self.run_before(&mut input)?;
+        let input = Turn::user(input.trim());
+
+        if let Some(reply) = reply {
+            let saved = self.record(input, Turn::assistant(&reply));
+            return Ok(with_save_warning(Response::new(&reply), saved));
+        }
+
+        self.recalled = match &self.long_term_memory {
+            Some(memory) => self.recall(memory.as_ref(), &input.content)?,
+            None => Vec::new(),
         };
 
-        let response = self.model.complete(&prompt)?;
+        let start = Instant::now();
+        self.fit_history(&input)?;
+        let prompt = self.build_prompt(&input)?;
 
-        self.conversation.push(prompt);
-        self.conversation.push(response.clone());
+        let (completion, usage) = self
+            .model
+            .complete_with_usage(&prompt, &self.formatter.stop_sequences())?;
+        let text = completion.trim().to_string();
 
-        if self.conversation.len() > self.conversation_limit {
-            self.conversation = self
-                .conversation
-                .split_off(self.conversation.len() - self.conversation_limit);
+        let mut response = Response::new(&text)
+            .with_metadata(keys::LATENCY_MS, start.elapsed().as_millis() as i64);
+        if let Some(usage) = usage {
+            response.set_metadata(keys::PROMPT_TOKENS, usage.prompt_tokens);
+            response.set_metadata(keys::COMPLETION_TOKENS, usage.completion_tokens);
+        }
+        if !self.recalled.is_empty() {
+            response.set_metadata(keys::SOURCES, self.recalled.clone());
         }
 
-        let final_result = self
-            .postprocessors
-            .iter()
-            .try_fold(response, |response, postprocessor| postprocessor(&response))?;
+        let content = input.content.clone();
+        let saved = self.record(input, Turn::assistant(&text));
 
-        Ok(final_result)
+        let response = self.run_after(&content, response)?;
+        Ok(with_save_warning(response, saved))
+    }
+}
+
+fn with_save_warning(response: Response, saved: Result<(), Box<dyn Error>>) -> Response {
+    match saved {
+        Ok(()) => response,
+        Err(err) => {
+            response.with_metadata(keys::WARNING, format!("Unable to save session: {}", err))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model_traits::TokenUsage;
+    use crate::response::MetadataValue;
+    use std::sync::{Arc, Mutex};
 
     struct MockCompletionModel;
 
@@ -139,14 +880,591 @@ mod tests {
         }
     }
 
+    /// Replies with the number of lines in the prompt.
+    struct LineCountModel;
+
+    impl CompletionModel for LineCountModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            Ok(format!(" {}\n", prompt.lines().count()))
+        }
+    }
+
     #[test]
     fn test_chatbot() {
-        let mut chatbot = Chatbot::builder(MockCompletionModel).build();
+        let mut chatbot = Chatbot::builder(MockCompletionModel).build().unwrap();
+
+        let response = chatbot.respond("Hello").unwrap().text;
+        assert_eq!(response, "User: Hello\nAssistant:");
+
+        let response = chatbot.respond("How are you?\n").unwrap().text;
+        assert_eq!(
+            response,
+            "User: Hello\nAssistant: User: Hello\nAssistant:\nUser: How are you?\nAssistant:"
+        );
+    }
+
+    /// Reports the number of prompt lines as the prompt's token usage.
+    struct UsageModel;
+
+    impl CompletionModel for UsageModel {
+        fn complete(&self, _prompt: &str) -> Result<String, Box<dyn Error>> {
+            Ok(" Hi".to_string())
+        }
+
+        fn complete_with_usage(
+            &self,
+            prompt: &str,
+            stop: &[String],
+        ) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+            let usage = TokenUsage {
+                prompt_tokens: prompt.lines().count() as u32,
+                completion_tokens: 1,
+            };
+            Ok((self.complete_with_stop(prompt, stop)?, Some(usage)))
+        }
+    }
+
+    #[test]
+    fn test_usage_is_reported_per_response() {
+        let model = Arc::new(UsageModel);
+        let mut first = Chatbot::builder(model.clone()).build().unwrap();
+        let mut second = Chatbot::builder(model).build().unwrap();
+
+        first.respond("Hello").unwrap();
+        let response = first.respond("Hello again").unwrap();
+        assert_eq!(
+            response.metadata(keys::PROMPT_TOKENS),
+            Some(&MetadataValue::from(4u32))
+        );
+
+        let response = second.respond("Hello").unwrap();
+        assert_eq!(
+            response.metadata(keys::PROMPT_TOKENS),
+            Some(&MetadataValue::from(2u32))
+        );
+        assert_eq!(
+            response.metadata(keys::COMPLETION_TOKENS),
+            Some(&MetadataValue::from(1u32))
+        );
+    }
+
+    #[test]
+    fn test_token_budget() {
+        let chatbot = Chatbot::builder(MockCompletionModel)
+            .context_size(100)
+            .max_tokens(20)
+            .prefix("abcdefgh")
+            .build()
+            .unwrap();
+
+        // 100 - 20 - 2 (prefix) - 3 ("Assistant:")
+        assert_eq!(chatbot.token_budget(), 75);
+    }
+
+    #[test]
+    fn test_history_truncated_to_budget() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .context_size(40)
+            .max_tokens(10)
+            .system("Always keep this.")
+            .build()
+            .unwrap();
+
+        let mut turns = Vec::new();
+        for i in 0..10 {
+            let input = format!("message {} of ten", i);
+            let reply = chatbot.respond(&input).unwrap().text;
+            turns.extend([Turn::user(&input), Turn::assistant(&reply)]);
+        }
+
+        // The oldest turns were dropped and the rest kept word for word.
+        let kept = chatbot.conversation();
+        assert!(!kept.is_empty() && kept.len() < turns.len());
+        assert_eq!(kept, &turns[turns.len() - kept.len()..]);
+        // The history before the last input fit the prompt for it.
+        let (history, last) = kept.split_at(kept.len() - 2);
+        let allowance = chatbot.history_allowance(&last[0]).unwrap();
+        assert!(chatbot.count_turns(history) <= allowance);
+        assert_eq!(chatbot.pinned()[0].content, "Always keep this.");
+
+        let long_input = "word ".repeat(100);
+        assert!(chatbot.respond(&long_input).is_err());
+    }
+
+    #[test]
+    fn test_compress_oldest() {
+        let mut chatbot = Chatbot::builder(MockCompletionModel)
+            .context_size(40)
+            .max_tokens(10)
+            .truncation(TruncationStrategy::CompressOldest { max_turn_tokens: 3 })
+            .build()
+            .unwrap();
+        chatbot.conversation = vec![
+            Turn::user("a b c d e f g h i j"),
+            Turn::assistant("k l m n o p q r s t"),
+        ];
+
+        chatbot.fit_history(&Turn::user("u v w x y z")).unwrap();
+
+        assert_eq!(chatbot.conversation().len(), 2);
+        assert_eq!(chatbot.conversation()[0].content, "a b...");
+    }
+
+    struct SummaryModel;
+
+    impl CompletionModel for SummaryModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            Ok(format!("{} lines summarized", prompt.lines().count()))
+        }
+    }
+
+    /// Keeps the user's lines from the summary prompt, as a stand-in for a summary.
+    struct UserLinesModel;
+
+    impl CompletionModel for UserLinesModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            let lines: Vec<&str> = prompt
+                .lines()
+                .flat_map(|line| line.split(" / "))
+                .filter(|line| line.starts_with("User:"))
+                .collect();
+            Ok(lines.join(" / "))
+        }
+    }
+
+    #[test]
+    fn test_summary_memory() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .context_size(60)
+            .max_tokens(10)
+            .summary_memory(SummaryMemory::new(UserLinesModel).with_prompt("{summary}\n{lines}"))
+            .build()
+            .unwrap();
+
+        let mut last = Vec::new();
+        for i in 0..8 {
+            let input = format!("message {}", i);
+            let reply = chatbot.respond(&input).unwrap().text;
+            last = vec![Turn::user(&input), Turn::assistant(&reply)];
+        }
+
+        // The oldest turns were folded into the summary, nothing was lost, and the
+        // latest exchange is kept word for word.
+        let summary = chatbot.summary().unwrap().to_string();
+        assert!(summary.starts_with("User: message 0 / User: message 1"));
+        for i in 0..8 {
+            let message = format!("message {}", i);
+            let in_history = chatbot.conversation().iter().any(|t| t.content == message);
+            assert!(in_history != summary.contains(&format!("User: {}", message)));
+        }
+        assert!(chatbot.conversation().ends_with(&last));
+
+        let prompt = chatbot.build_prompt(&Turn::user("next")).unwrap();
+        assert!(prompt.starts_with(&format!(
+            "Summary of the conversation so far:\n{}\n",
+            summary
+        )));
+    }
+
+    struct FailingModel;
+
+    impl CompletionModel for FailingModel {
+        fn complete(&self, _prompt: &str) -> Result<String, Box<dyn Error>> {
+            Err("model unavailable".into())
+        }
+    }
+
+    #[test]
+    fn test_summary_failure_keeps_history() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .context_size(60)
+            .max_tokens(10)
+            .summary_memory(SummaryMemory::new(FailingModel))
+            .build()
+            .unwrap();
+
+        while chatbot.respond("one two three four").is_ok() {}
+
+        let turns = chatbot.conversation().len();
+        assert!(turns > 0);
+        assert!(chatbot.respond("one two three four").is_err());
+        assert_eq!(chatbot.conversation().len(), turns);
+        assert_eq!(chatbot.summary(), None);
+    }
+
+    #[test]
+    fn test_conversation_limit_summarizes() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .conversation_limit(2)
+            .summary_memory(SummaryMemory::new(SummaryModel).with_prompt("{lines}"))
+            .build()
+            .unwrap();
+        chatbot.respond("Hi").unwrap();
+        chatbot.respond("Hi").unwrap();
+
+        assert_eq!(chatbot.conversation().len(), 2);
+        assert_eq!(chatbot.summary(), Some("2 lines summarized"));
+
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .conversation_limit(2)
+            .summary_memory(SummaryMemory::new(FailingModel))
+            .build()
+            .unwrap();
+        chatbot.respond("Hi").unwrap();
+        chatbot.respond("Hi").unwrap();
+
+        assert_eq!(chatbot.conversation().len(), 4);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .prefix("Be helpful.\n")
+            .system("Pinned")
+            .build()
+            .unwrap();
+        chatbot.respond("Hi").unwrap();
+
+        let mut restored = Chatbot::builder(LineCountModel).build().unwrap();
+        restored.restore(chatbot.state()).unwrap();
+
+        assert_eq!(restored.state(), chatbot.state());
+        assert_eq!(
+            restored.build_prompt(&Turn::user("Next")).unwrap(),
+            chatbot.build_prompt(&Turn::user("Next")).unwrap()
+        );
+    }
+
+    struct FixedMemory(Vec<String>);
+
+    impl LongTermMemory for FixedMemory {
+        fn recall(&self, _query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(self.0.clone())
+        }
 
-        let response = chatbot.respond("Hello").unwrap();
-        assert_eq!(response, "Hello");
+        fn remember(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+            self.0.push(text.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_long_term_memory() {
+        let memory = FixedMemory(vec!["User: my dog is Rex\nAssistant: Nice".to_string()]);
+        let mut chatbot = Chatbot::builder(MockCompletionModel)
+            .long_term_memory(memory)
+            .build()
+            .unwrap();
+
+        let response = chatbot.respond("What is my dog called?").unwrap().text;
+        assert_eq!(
+            response,
+            "Relevant memories from earlier conversations:\n- User: my dog is Rex / Assistant: Nice\nUser: What is my dog called?\nAssistant:"
+        );
+
+        // The exchange just remembered is still in the history, so it isn't recalled.
+        let response = chatbot.respond("And my cat?").unwrap();
+        assert_eq!(
+            response.metadata(keys::SOURCES),
+            Some(&MetadataValue::from(vec![
+                "User: my dog is Rex\nAssistant: Nice"
+            ]))
+        );
+    }
+
+    struct ForgetfulMemory;
+
+    impl LongTermMemory for ForgetfulMemory {
+        fn recall(&self, _query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+
+        fn remember(&mut self, _text: &str) -> Result<(), Box<dyn Error>> {
+            Err("disk full".into())
+        }
+    }
+
+    #[test]
+    fn test_remember_failure_keeps_response() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .long_term_memory(ForgetfulMemory)
+            .build()
+            .unwrap();
+
+        assert_eq!(chatbot.respond("Hi").unwrap().text, "2");
+        assert_eq!(chatbot.conversation().len(), 2);
+    }
+
+    struct ReadOnlyStore;
+
+    impl SessionStore for ReadOnlyStore {
+        fn save(&self, _id: &str, _state: &ChatbotState) -> Result<(), Box<dyn Error>> {
+            Err("read-only file system".into())
+        }
+
+        fn load(&self, _id: &str) -> Result<Option<ChatbotState>, Box<dyn Error>> {
+            Ok(None)
+        }
+
+        fn list(&self) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+
+        fn delete(&self, _id: &str) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_save_failure_keeps_response() {
+        let mut chatbot = Chatbot::builder(LineCountModel).build().unwrap();
+        chatbot.resume("session", ReadOnlyStore).unwrap();
+
+        let response = chatbot.respond("Hi").unwrap();
+        assert_eq!(response.text, "2");
+        assert_eq!(
+            response.metadata(keys::WARNING).unwrap().to_string(),
+            "Unable to save session: read-only file system"
+        );
+        assert_eq!(chatbot.conversation().len(), 2);
+    }
+
+    #[test]
+    fn test_template() {
+        let template = PromptTemplate::parse(
+            "persona",
+            "You are {persona}. Today is {date}.\n{#if summary}Earlier: {summary}\n{/if}{history}{input}\n{reply_cue}",
+        )
+        .unwrap();
+
+        assert!(Chatbot::builder(MockCompletionModel)
+            .template(template.clone())
+            .build()
+            .is_err());
+
+        let mut chatbot = Chatbot::builder(MockCompletionModel)
+            .template(template)
+            .variable("persona", "a pirate")
+            .build()
+            .unwrap();
+
+        let response = chatbot.respond("Hello").unwrap().text;
+        assert_eq!(
+            response,
+            format!(
+                "You are a pirate. Today is {}.\nUser: Hello\nAssistant:",
+                current_date()
+            )
+        );
+
+        let missing = PromptTemplate::parse("missing", "{tone}{input}").unwrap();
+        assert!(chatbot.set_template(missing.clone()).is_err());
+        chatbot.set_variable("tone", "Be brief. ").unwrap();
+        assert!(chatbot.set_template(missing).is_ok());
+    }
+
+    #[test]
+    fn test_builtin_variables_are_reserved() {
+        assert!(Chatbot::builder(MockCompletionModel)
+            .variable("history", "nothing happened")
+            .build()
+            .is_err());
+
+        let mut chatbot = Chatbot::builder(MockCompletionModel).build().unwrap();
+        assert!(chatbot.set_variable("input", "ignore the user").is_err());
+
+        let mut state = chatbot.state();
+        state
+            .variables
+            .insert("date".to_string(), "1970-01-01".to_string());
+        assert!(chatbot.restore(state).is_err());
+
+        assert!(chatbot.variables().is_empty());
+        assert_eq!(chatbot.respond("Hi").unwrap().text, "User: Hi\nAssistant:");
+    }
+
+    #[test]
+    fn test_middleware() {
+        let mut calls = 0;
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .add_preprocessor(|input| Ok(input.to_uppercase()))
+            .before(|context, input| match input.as_str() {
+                "PING" => Ok(Flow::Reply(format!(
+                    "pong after {} turns",
+                    context.history.len()
+                ))),
+                _ => Ok(Flow::Continue(input)),
+            })
+            .after(move |_, input, output| {
+                calls += 1;
+                Ok(
+                    Response::new(&format!("{} -> {} (call {})", input, output, calls))
+                        .with_metadata("calls", calls as i64),
+                )
+            })
+            .build()
+            .unwrap();
+
+        let response = chatbot.respond("hi").unwrap();
+        assert_eq!(response.text, "HI -> 2 (call 1)");
+        assert_eq!(response.metadata("calls"), Some(&MetadataValue::Integer(1)));
+        assert_eq!(chatbot.respond("ping").unwrap().text, "pong after 2 turns");
+        assert_eq!(chatbot.respond("hey").unwrap().text, "HEY -> 6 (call 2)");
+
+        let contents: Vec<&str> = chatbot
+            .conversation()
+            .iter()
+            .map(|turn| turn.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec!["HI", "2", "PING", "pong after 2 turns", "HEY", "6"]
+        );
+    }
+
+    struct StubbornModel;
+
+    impl CompletionModel for StubbornModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            match prompt.contains("could not be parsed") {
+                true => Ok(r#" {"answer": 4}"#.to_string()),
+                false => Ok(" Four, obviously.".to_string()),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Answer {
+        answer: i32,
+    }
 
-        let response = chatbot.respond("How are you?").unwrap();
-        assert_eq!(response, "Hello\nHello\nHow are you?");
+    #[test]
+    fn test_respond_as() {
+        let mut chatbot = Chatbot::builder(StubbornModel).build().unwrap();
+
+        let answer: Answer = chatbot
+            .respond_as("What is 2 + 2?", &StructuredOutput::json())
+            .unwrap();
+        assert_eq!(answer.answer, 4);
+
+        let contents: Vec<&str> = chatbot
+            .conversation()
+            .iter()
+            .map(|turn| turn.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["What is 2 + 2?", r#"{"answer": 4}"#]);
+
+        let failed: Result<Answer, _> =
+            chatbot.respond_as("And 3 + 3?", &StructuredOutput::json().with_max_retries(0));
+        assert!(failed.is_err());
+        assert_eq!(chatbot.conversation().len(), 2);
+    }
+
+    struct SharedMemory(Arc<Mutex<Vec<String>>>);
+
+    impl LongTermMemory for SharedMemory {
+        fn recall(&self, _query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(Vec::new())
+        }
+
+        fn remember(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(text.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_respond_as_records_only_the_answer() {
+        let remembered = Arc::new(Mutex::new(Vec::new()));
+        let mut after_calls = 0;
+        let mut chatbot = Chatbot::builder(StubbornModel)
+            .context_size(120)
+            .max_tokens(10)
+            .summary_memory(SummaryMemory::new(SummaryModel))
+            .long_term_memory(SharedMemory(remembered.clone()))
+            .after(move |_, _, output| {
+                after_calls += 1;
+                Ok(Response::new(&format!(
+                    "{} (after {})",
+                    output, after_calls
+                )))
+            })
+            .build()
+            .unwrap();
+
+        let answer: Answer = chatbot
+            .respond_as(
+                "What is 2 + 2?",
+                &StructuredOutput::json().with_max_retries(5),
+            )
+            .unwrap();
+        assert_eq!(answer.answer, 4);
+
+        let exchange = "User: What is 2 + 2?\nAssistant: {\"answer\": 4}";
+        assert_eq!(*remembered.lock().unwrap(), vec![exchange]);
+        assert_eq!(chatbot.summary(), None);
+        assert_eq!(chatbot.formatter.render(chatbot.conversation()), exchange);
+    }
+
+    #[test]
+    fn test_styles() {
+        let styles = StyleHandle::new();
+        let mut chatbot = Chatbot::builder(MockCompletionModel)
+            .prefix("You are a chatbot.\n")
+            .styles(styles.clone())
+            .conversation_limit(0)
+            .build()
+            .unwrap();
+
+        styles.push("Talk like a pirate.");
+        assert_eq!(
+            chatbot.respond("Hi").unwrap().text,
+            "You are a chatbot.\nTalk like a pirate.\nUser: Hi\nAssistant:"
+        );
+
+        chatbot.styles().reset();
+        assert_eq!(
+            chatbot.respond("Hi").unwrap().text,
+            "You are a chatbot.\nUser: Hi\nAssistant:"
+        );
+    }
+
+    #[test]
+    fn test_restore_keeps_default_styles() {
+        let styles = StyleHandle::with_styles(vec!["Talk like a pirate.".to_string()]);
+        let mut chatbot = Chatbot::builder(MockCompletionModel)
+            .styles(styles.clone())
+            .build()
+            .unwrap();
+
+        // Saved before styles were part of the state.
+        let state: ChatbotState =
+            serde_json::from_str(r#"{"conversation": [], "pinned": []}"#).unwrap();
+        chatbot.restore(state).unwrap();
+        assert_eq!(styles.styles(), vec!["Talk like a pirate."]);
+
+        styles.reset();
+        chatbot.restore(chatbot.state()).unwrap();
+        assert!(styles.styles().is_empty());
+    }
+
+    #[test]
+    fn test_history_stores_only_new_turns() {
+        let mut chatbot = Chatbot::builder(LineCountModel)
+            .prefix("Be helpful.\n")
+            .conversation_limit(4)
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            chatbot.respond("Hi").unwrap();
+        }
+
+        let contents: Vec<&str> = chatbot
+            .conversation()
+            .iter()
+            .map(|turn| turn.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["Hi", "5", "Hi", "7"]);
     }
 }
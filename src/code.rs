@@ -0,0 +1,127 @@
+pub mod approval;
+pub mod executor;
+pub mod extract;
+pub mod responder;
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use self::extract::canonical_language;
+
+/// A language generated code can be run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Bash,
+    Python,
+    Node,
+    Rust,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [
+        Language::Bash,
+        Language::Python,
+        Language::Node,
+        Language::Rust,
+    ];
+
+    /// The language for a code block tag such as `py` or `sh`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match canonical_language(tag).as_str() {
+            "bash" => Some(Language::Bash),
+            "python" => Some(Language::Python),
+            "javascript" => Some(Language::Node),
+            "rust" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    /// The language a task asks for by name, or else one suited to it: tasks about
+    /// files and directories are shell work. `None` if nothing in the task hints at
+    /// a language.
+    pub fn from_task(task: &str) -> Option<Self> {
+        let task = task.to_lowercase();
+        let words: Vec<&str> = task
+            .split(|c: char| !c.is_alphanumeric() && c != '.')
+            .filter(|word| !word.is_empty())
+            .collect();
+        let mentions = |names: &[&str]| words.iter().any(|word| names.contains(word));
+
+        if mentions(&["bash", "shell", "sh", "terminal", "command", "one-liner"]) {
+            Some(Language::Bash)
+        } else if mentions(&["python", "python3", "py"]) {
+            Some(Language::Python)
+        } else if mentions(&["node", "node.js", "nodejs", "javascript", "js"]) {
+            Some(Language::Node)
+        } else if mentions(&["rust", "cargo"]) {
+            Some(Language::Rust)
+        } else if mentions(&[
+            "file",
+            "files",
+            "directory",
+            "directories",
+            "folder",
+            "disk",
+        ]) {
+            Some(Language::Bash)
+        } else {
+            None
+        }
+    }
+
+    /// The canonical code block tag.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Language::Bash => "bash",
+            Language::Python => "python",
+            Language::Node => "javascript",
+            Language::Rust => "rust",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Language::Bash => "text/x-shellscript",
+            Language::Python => "text/x-python",
+            Language::Node => "text/javascript",
+            Language::Rust => "text/x-rust",
+        }
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Language::Bash => "bash",
+            Language::Python => "Python",
+            Language::Node => "Node.js",
+            Language::Rust => "Rust",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_from_task() {
+        assert_eq!(
+            Language::from_task("count files in this directory"),
+            Some(Language::Bash)
+        );
+        assert_eq!(
+            Language::from_task("Using node.js, reverse a string"),
+            Some(Language::Node)
+        );
+        assert_eq!(
+            Language::from_task("write it in Rust please"),
+            Some(Language::Rust)
+        );
+        assert_eq!(Language::from_task("I don't trust this number"), None);
+        assert_eq!(Language::from_tag("py"), Some(Language::Python));
+    }
+}
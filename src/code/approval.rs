@@ -0,0 +1,898 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::Language;
+
+/// What a deny list entry matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DenyRule {
+    /// A plain substring of the code.
+    Pattern(String),
+    /// A shell command, as a word anywhere in the code: other languages can run
+    /// commands through a shell too.
+    Command(String),
+    /// A shell command run with `-r`, `-R` or `--recursive`, however the flags are
+    /// written.
+    Recursive(String),
+    /// A top-level module imported by Python or Node code, or a `std` module used
+    /// by Rust code.
+    Module(Language, String),
+    /// A name anywhere in code in the language, such as a function.
+    Identifier(Language, String),
+}
+
+impl DenyRule {
+    pub fn matches(&self, language: Language, code: &str) -> bool {
+        match self {
+            DenyRule::Pattern(pattern) => code.contains(pattern.as_str()),
+            DenyRule::Command(command) => {
+                shell_words(code).any(|(_, word)| word == command.as_str())
+            }
+            DenyRule::Recursive(command) => shell_words(code)
+                .filter(|(_, word)| *word == command.as_str())
+                .any(|(index, word)| {
+                    let rest = &code[index + word.len()..];
+                    let statement =
+                        &rest[..rest.find(['\n', ';', '|', '&', ')']).unwrap_or(rest.len())];
+                    shell_words(statement)
+                        .map(|(_, word)| word)
+                        .take_while(|word| *word != "--")
+                        .any(is_recursive_flag)
+                }),
+            DenyRule::Module(rule_language, module) => {
+                *rule_language == language
+                    && imported_modules(language, code).contains(&module.as_str())
+            }
+            DenyRule::Identifier(rule_language, name) => {
+                *rule_language == language
+                    && identifiers(code).any(|(_, word)| word == name.as_str())
+            }
+        }
+    }
+}
+
+impl Display for DenyRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DenyRule::Pattern(pattern) => write!(f, "{}", pattern.trim()),
+            DenyRule::Recursive(command) => write!(f, "{} -r", command),
+            DenyRule::Command(name) | DenyRule::Module(_, name) | DenyRule::Identifier(_, name) => {
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
+
+/// Commands, modules and functions that are never run, with the reason given to
+/// the user.
+pub fn default_deny_list() -> Vec<(DenyRule, String)> {
+    let network = "accesses the network";
+    let programs = "runs other programs";
+    let recursive = |command: &str, reason| (DenyRule::Recursive(command.to_string()), reason);
+    let command = |command: &str, reason| (DenyRule::Command(command.to_string()), reason);
+    let module =
+        |language, module: &str, reason| (DenyRule::Module(language, module.to_string()), reason);
+    let identifier =
+        |language, name: &str, reason| (DenyRule::Identifier(language, name.to_string()), reason);
+
+    let mut rules = vec![
+        recursive("rm", "deletes files recursively"),
+        identifier(Language::Python, "rmtree", "deletes files recursively"),
+        identifier(
+            Language::Rust,
+            "remove_dir_all",
+            "deletes files recursively",
+        ),
+        command("mkfs", "formats a filesystem"),
+        command("dd", "writes raw devices"),
+        (DenyRule::Pattern(":(){".to_string()), "is a fork bomb"),
+        command("sudo", "runs as root"),
+        recursive("chmod", "changes permissions recursively"),
+        recursive("chown", "changes ownership recursively"),
+        (DenyRule::Pattern("/dev/tcp/".to_string()), network),
+        module(Language::Python, "subprocess", programs),
+        module(Language::Node, "child_process", programs),
+        module(Language::Rust, "net", network),
+    ];
+    for name in ["curl", "wget", "ssh", "scp", "telnet"] {
+        rules.push(command(name, network));
+    }
+    for name in [
+        "socket",
+        "socketserver",
+        "ssl",
+        "http",
+        "urllib",
+        "urllib3",
+        "ftplib",
+        "smtplib",
+        "poplib",
+        "imaplib",
+        "nntplib",
+        "telnetlib",
+        "xmlrpc",
+        "requests",
+        "httpx",
+        "aiohttp",
+    ] {
+        rules.push(module(Language::Python, name, network));
+    }
+    for name in ["http", "https", "http2", "net", "dgram", "tls", "dns"] {
+        rules.push(module(Language::Node, name, network));
+    }
+    for name in ["fetch", "WebSocket", "XMLHttpRequest"] {
+        rules.push(identifier(Language::Node, name, network));
+    }
+
+    rules
+        .into_iter()
+        .map(|(rule, reason)| (rule, reason.to_string()))
+        .collect()
+}
+
+/// The outcome of an approval check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "decision", content = "reason", rename_all = "snake_case")]
+pub enum Decision {
+    Approved,
+    Denied(String),
+    /// Dry-run mode: show the code, don't run it.
+    DryRun,
+}
+
+/// Asks a human whether code may run.
+pub trait Approver: Send + Sync {
+    fn approve(&self, language: Language, code: &str) -> Result<bool, Box<dyn Error>>;
+}
+
+impl<F> Approver for F
+where
+    F: Fn(Language, &str) -> Result<bool, Box<dyn Error>> + Send + Sync,
+{
+    fn approve(&self, language: Language, code: &str) -> Result<bool, Box<dyn Error>> {
+        self(language, code)
+    }
+}
+
+/// Shows the code in the terminal and asks for confirmation on stdin.
+pub struct CliApprover;
+
+impl Approver for CliApprover {
+    fn approve(&self, language: Language, code: &str) -> Result<bool, Box<dyn Error>> {
+        println!("The assistant wants to run this {} code:\n", language);
+        for line in code.lines() {
+            println!("    {}", line);
+        }
+        print!("\nRun it? [y/N] ");
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+/// Commands and modules that are safe enough to run without asking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allowlist {
+    /// Shell commands, checked for every command in a bash script.
+    pub commands: HashSet<String>,
+    /// Python modules, Node modules and Rust `std` modules that may be imported.
+    pub modules: HashSet<String>,
+}
+
+impl Default for Allowlist {
+    fn default() -> Self {
+        let commands = [
+            "echo", "printf", "ls", "wc", "cat", "head", "tail", "grep", "sort", "uniq", "cut",
+            "tr", "find", "du", "df", "date", "pwd", "basename", "dirname", "expr", "seq", "stat",
+            "file", "test", "true", "false", "[",
+        ];
+        let modules = [
+            // Python
+            "math",
+            "cmath",
+            "statistics",
+            "random",
+            "decimal",
+            "fractions",
+            "datetime",
+            "time",
+            "calendar",
+            "json",
+            "re",
+            "string",
+            "collections",
+            "itertools",
+            "functools",
+            "operator",
+            "textwrap",
+            "heapq",
+            "bisect",
+            "typing",
+            "dataclasses",
+            // Node
+            "util",
+            "assert",
+            // Rust std
+            "collections",
+            "fmt",
+            "cmp",
+            "iter",
+            "str",
+            "string",
+            "vec",
+            "ops",
+            "num",
+            "char",
+            "convert",
+            "mem",
+            "time",
+            "f64",
+            "i64",
+            "u64",
+        ];
+
+        Self {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            modules: modules.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+}
+
+impl Allowlist {
+    /// Whether `code` only uses allowlisted commands or modules. The check is
+    /// conservative: anything it can't account for fails.
+    pub fn permits(&self, language: Language, code: &str) -> bool {
+        match language {
+            Language::Bash => self.permits_bash(code),
+            Language::Python => self.permits_python(code),
+            Language::Node => self.permits_node(code),
+            Language::Rust => self.permits_rust(code),
+        }
+    }
+
+    fn permits_bash(&self, code: &str) -> bool {
+        // Redirects can overwrite files, substitutions hide commands, and `find` can
+        // run, delete or write things.
+        let unsafe_syntax = ['>', '`'];
+        let unsafe_words = [
+            "$(", "<(", ">(", "-exec", "-delete", "-ok", "-fprint", "-fls",
+        ];
+        if code.contains(unsafe_syntax) || unsafe_words.iter().any(|word| code.contains(word)) {
+            return false;
+        }
+
+        code.split(['\n', ';', '|', '&'])
+            .map(|segment| {
+                segment
+                    .split_whitespace()
+                    .skip_while(|word| word.contains('='))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|words| {
+                words
+                    .first()
+                    .is_some_and(|command| !command.starts_with('#'))
+            })
+            .all(|words| self.commands.contains(words[0]) && !writes_files(words[0], &words[1..]))
+    }
+
+    fn permits_python(&self, code: &str) -> bool {
+        // Builtins that open files or run code, modules that allowlisted modules
+        // import and expose as attributes, and private or dunder attributes that
+        // reach the same things through objects. Matched as identifiers wherever
+        // they appear, so `open (...)` or `f = open` fail too.
+        let unsafe_names = [
+            "open",
+            "exec",
+            "eval",
+            "compile",
+            "getattr",
+            "setattr",
+            "delattr",
+            "globals",
+            "locals",
+            "vars",
+            "breakpoint",
+            "sys",
+            "os",
+            "builtins",
+            "inspect",
+            "codecs",
+            "types",
+        ];
+        let safe_dunders = [
+            "__name__", "__main__", "__init__", "__repr__", "__str__", "__eq__", "__hash__",
+            "__lt__", "__len__", "__iter__", "__next__",
+        ];
+        let unsafe_word = |(index, word): (usize, &str)| {
+            let dunder = word.len() > 4 && word.starts_with("__") && word.ends_with("__");
+            let private_attribute =
+                word.starts_with('_') && code[..index].trim_end().ends_with('.');
+            unsafe_names.contains(&word)
+                || (dunder && !safe_dunders.contains(&word))
+                || private_attribute
+        };
+        if identifiers(code).any(unsafe_word) {
+            return false;
+        }
+
+        code.lines().map(str::trim).all(|line| {
+            let keywords = identifiers(line)
+                .filter(|(_, word)| *word == "import" || *word == "from")
+                .count();
+            if keywords == 0 || line.starts_with('#') {
+                return true;
+            }
+            // Only plain import statements on a line of their own can be checked, not
+            // ones after `;` or `:`, or hidden elsewhere in the line.
+            if line.contains(';') {
+                return false;
+            }
+
+            let modules = match (line.strip_prefix("import "), line.strip_prefix("from ")) {
+                (Some(modules), _) if keywords == 1 => modules.split(',').collect::<Vec<_>>(),
+                (None, Some(module)) if keywords == 2 => {
+                    vec![module.split_whitespace().next().unwrap_or("")]
+                }
+                _ => return false,
+            };
+
+            modules.iter().all(|module| {
+                let module = module.split_whitespace().next().unwrap_or("");
+                let root = module.split('.').next().unwrap_or("");
+                self.modules.contains(root)
+            })
+        })
+    }
+
+    fn permits_node(&self, code: &str) -> bool {
+        // `process`, `module`, `global` and the CommonJS `arguments` reach the
+        // environment, child processes and `require` without an import, and
+        // `constructor` reaches `Function`. Matched as identifiers wherever they
+        // appear, so `Function (...)` fails too.
+        let unsafe_names = [
+            "eval",
+            "Function",
+            "constructor",
+            "process",
+            "module",
+            "global",
+            "globalThis",
+            "arguments",
+        ];
+
+        identifiers(code).all(|(index, word)| {
+            let rest = code[index + word.len()..].trim_start();
+            let permits = |module: &str| self.modules.contains(module.trim_start_matches("node:"));
+            match word {
+                _ if unsafe_names.contains(&word) => false,
+                // Only `require('name')` with a literal name can be checked, not
+                // `require` passed around or called with an expression.
+                "require" => rest
+                    .strip_prefix('(')
+                    .and_then(|rest| quoted(rest.trim_start()))
+                    .is_some_and(|(module, rest)| rest.starts_with(')') && permits(module)),
+                // `import 'name'`; `import x from 'name'` is checked at `from`, and
+                // `import(...)` can't be checked at all.
+                "import" => match quoted(rest) {
+                    Some((module, _)) => permits(module),
+                    None => !rest.starts_with(['(', '.']),
+                },
+                "from" => quoted(rest).is_none_or(|(module, _)| permits(module)),
+                _ => true,
+            }
+        })
+    }
+
+    fn permits_rust(&self, code: &str) -> bool {
+        if code.contains("unsafe") || code.contains("extern") || code.contains("include!") {
+            return false;
+        }
+
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        code.match_indices("std").all(|(index, _)| {
+            let before = code[..index].chars().next_back();
+            let rest = &code[index + "std".len()..];
+            if before.is_some_and(is_ident) || rest.starts_with(is_ident) {
+                return true;
+            }
+
+            // `std` on its own, as in `use std as s;` or `std :: fs`, can't be checked.
+            let Some(rest) = rest.strip_prefix("::") else {
+                return false;
+            };
+            let module: String = rest.chars().take_while(|c| is_ident(*c)).collect();
+            // `use std::{...}` lists modules we can't check one by one.
+            !module.is_empty() && self.modules.contains(&module)
+        })
+    }
+}
+
+/// Whether `args` make an allowlisted command write files: `sort -o` and
+/// `--compress-program`, an output operand to `uniq`, and `file -C`.
+fn writes_files(command: &str, args: &[&str]) -> bool {
+    let short = |flag: char| {
+        args.iter()
+            .any(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg.contains(flag))
+    };
+    // Long options can be abbreviated to any unambiguous prefix.
+    let long = |name: &str| {
+        args.iter().any(|arg| {
+            let option = arg.strip_prefix("--").and_then(|arg| arg.split('=').next());
+            option.is_some_and(|option| !option.is_empty() && name.starts_with(option))
+        })
+    };
+
+    match command {
+        "sort" => short('o') || long("output") || long("compress-program"),
+        "uniq" => {
+            let operands = args
+                .iter()
+                .filter(|arg| **arg == "-" || !arg.starts_with('-'))
+                .count();
+            operands > 1 || args.contains(&"--")
+        }
+        "file" => short('C') || long("compile"),
+        _ => false,
+    }
+}
+
+/// The identifiers in `code` and where they start. Strings and comments aren't
+/// skipped, so words inside them count too.
+fn identifiers(code: &str) -> impl Iterator<Item = (usize, &str)> {
+    words(code, |c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Shell words in `code` and where they start: runs of letters, digits, `_` and
+/// `-`, so paths, quotes and punctuation around a command don't hide it.
+fn shell_words(code: &str) -> impl Iterator<Item = (usize, &str)> {
+    words(code, |c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn words(code: &str, is_word: fn(char) -> bool) -> impl Iterator<Item = (usize, &str)> {
+    code.char_indices()
+        .filter(move |(index, c)| is_word(*c) && !code[..*index].ends_with(is_word))
+        .map(move |(index, _)| {
+            let end = code[index..]
+                .find(|c: char| !is_word(c))
+                .map_or(code.len(), |end| index + end);
+            (index, &code[index..end])
+        })
+}
+
+/// Whether a shell word is `-r`, `-R` or `--recursive`, alone or among other flags.
+fn is_recursive_flag(word: &str) -> bool {
+    match word.strip_prefix("--") {
+        // Long options can be abbreviated to any unambiguous prefix.
+        Some(option) => !option.is_empty() && "recursive".starts_with(option),
+        None => word.starts_with('-') && word.contains(['r', 'R']),
+    }
+}
+
+/// The top-level modules `code` imports: from `import` and `from` statements and
+/// `__import__` or `import_module` calls in Python, from `require`, `import` and
+/// `from` in Node, and the names after `::` or in `use` declarations in Rust.
+/// Module names built at runtime aren't found.
+fn imported_modules(language: Language, code: &str) -> Vec<&str> {
+    fn root(module: &str) -> &str {
+        let module = module.trim_start_matches("node:");
+        module.split(['.', '/']).next().unwrap_or("")
+    }
+
+    let mut modules = Vec::new();
+    for (index, word) in identifiers(code) {
+        let rest = code[index + word.len()..].trim_start();
+        let called = rest
+            .strip_prefix('(')
+            .and_then(|rest| quoted(rest.trim_start()))
+            .map(|(module, _)| root(module));
+
+        match (language, word) {
+            (Language::Python, "import") => {
+                // A statement ends at `;` or a newline without a `\` before it.
+                let end = rest
+                    .char_indices()
+                    .find(|(i, c)| *c == ';' || (*c == '\n' && !rest[..*i].ends_with('\\')))
+                    .map_or(rest.len(), |(i, _)| i);
+                let names = rest[..end].split(',');
+                modules.extend(names.filter_map(|name| identifiers(name).next().map(|(_, n)| n)));
+            }
+            (Language::Python, "from") => {
+                modules.extend(identifiers(rest).next().map(|(_, name)| name));
+            }
+            (Language::Python, "__import__" | "import_module") => modules.extend(called),
+            (Language::Node, "require") => modules.extend(called),
+            (Language::Node, "import" | "from") => {
+                let module = quoted(rest).map(|(module, _)| root(module));
+                modules.extend(module.or(called));
+            }
+            (Language::Rust, "use") => {
+                let end = rest.find(';').unwrap_or(rest.len());
+                modules.extend(identifiers(&rest[..end]).map(|(_, name)| name));
+            }
+            (Language::Rust, _) if code[..index].trim_end().ends_with("::") => modules.push(word),
+            _ => {}
+        }
+    }
+    modules
+}
+
+/// The contents of the string literal `code` starts with, and the code after it.
+fn quoted(code: &str) -> Option<(&str, &str)> {
+    let quote = code
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '\'' | '"' | '`'))?;
+    let (contents, rest) = code[1..].split_once(quote)?;
+    Some((contents, rest.trim_start()))
+}
+
+/// One approval decision, as written to the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: u64,
+    pub language: String,
+    pub code: String,
+    /// `approved`, `denied` or `dry_run`.
+    pub decision: String,
+    /// Who or what decided: `deny_list`, `allowlist`, `user`, `dry_run` or `policy`.
+    pub decided_by: String,
+    pub reason: Option<String>,
+}
+
+/// Decides whether generated code may run.
+///
+/// Checks run in order: the deny list, dry-run mode, the allowlist (if enabled),
+/// then the approver. Without an approver, code that isn't auto-approved is denied.
+/// Every decision is appended to the audit log, if one is set, as a line of JSON.
+#[derive(Clone)]
+pub struct ApprovalPolicy {
+    approver: Option<Arc<dyn Approver>>,
+    dry_run: bool,
+    allowlist: Option<Allowlist>,
+    deny_list: Vec<(DenyRule, String)>,
+    audit_log: Option<PathBuf>,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            approver: None,
+            dry_run: false,
+            allowlist: None,
+            deny_list: default_deny_list(),
+            audit_log: None,
+        }
+    }
+}
+
+impl ApprovalPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_approver<A: Approver + 'static>(mut self, approver: A) -> Self {
+        self.approver = Some(Arc::new(approver));
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Runs code that passes `allowlist` without asking.
+    pub fn with_allowlist(mut self, allowlist: Allowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    /// Never runs code containing `pattern`.
+    pub fn with_denied(self, pattern: &str, reason: &str) -> Self {
+        self.with_deny_rule(DenyRule::Pattern(pattern.to_string()), reason)
+    }
+
+    /// Never runs code that `rule` matches.
+    pub fn with_deny_rule(mut self, rule: DenyRule, reason: &str) -> Self {
+        self.deny_list.push((rule, reason.to_string()));
+        self
+    }
+
+    pub fn with_audit_log<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    pub fn check(&self, language: Language, code: &str) -> Result<Decision, Box<dyn Error>> {
+        let (decision, decided_by) = self.decide(language, code)?;
+        self.audit(language, code, &decision, decided_by)?;
+        Ok(decision)
+    }
+
+    fn decide(
+        &self,
+        language: Language,
+        code: &str,
+    ) -> Result<(Decision, &'static str), Box<dyn Error>> {
+        let denied = self
+            .deny_list
+            .iter()
+            .find(|(rule, _)| rule.matches(language, code));
+        if let Some((rule, reason)) = denied {
+            let reason = format!("it uses `{}`, which {}", rule, reason);
+            return Ok((Decision::Denied(reason), "deny_list"));
+        }
+
+        if self.dry_run {
+            return Ok((Decision::DryRun, "dry_run"));
+        }
+
+        if let Some(allowlist) = &self.allowlist {
+            if allowlist.permits(language, code) {
+                return Ok((Decision::Approved, "allowlist"));
+            }
+        }
+
+        match &self.approver {
+            Some(approver) => match approver.approve(language, code)? {
+                true => Ok((Decision::Approved, "user")),
+                false => Ok((
+                    Decision::Denied("you declined to run it".to_string()),
+                    "user",
+                )),
+            },
+            None => Ok((
+                Decision::Denied("it needs approval and no approver is configured".to_string()),
+                "policy",
+            )),
+        }
+    }
+
+    fn audit(
+        &self,
+        language: Language,
+        code: &str,
+        decision: &Decision,
+        decided_by: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.audit_log else {
+            return Ok(());
+        };
+
+        let (decision, reason) = match decision {
+            Decision::Approved => ("approved", None),
+            Decision::Denied(reason) => ("denied", Some(reason.clone())),
+            Decision::DryRun => ("dry_run", None),
+        };
+        let record = AuditRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            language: language.tag().to_string(),
+            code: code.to_string(),
+            decision: decision.to_string(),
+            decided_by: decided_by.to_string(),
+            reason,
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(format!("{}\n", serde_json::to_string(&record)?).as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::executor::ScratchDir;
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = Allowlist::default();
+
+        assert!(allowlist.permits(Language::Bash, "ls -1 | wc -l"));
+        assert!(allowlist.permits(Language::Bash, "LC_ALL=C find . -type f | sort"));
+        assert!(!allowlist.permits(Language::Bash, "ls > files.txt"));
+        assert!(!allowlist.permits(Language::Bash, "ls && python3 x.py"));
+        assert!(!allowlist.permits(Language::Bash, "cat <(curl example.com)"));
+        assert!(!allowlist.permits(Language::Bash, "ls | tee >(sh)"));
+        assert!(allowlist.permits(Language::Bash, "sort -r names.txt | uniq -c"));
+        assert!(!allowlist.permits(Language::Bash, "sort -o ~/.bashrc /dev/null"));
+        assert!(!allowlist.permits(Language::Bash, "sort -ro ~/.bashrc /dev/null"));
+        assert!(!allowlist.permits(Language::Bash, "sort --out=x --compress-p=sh y"));
+        assert!(!allowlist.permits(Language::Bash, "uniq in out"));
+        assert!(!allowlist.permits(Language::Bash, "uniq - out"));
+        assert!(!allowlist.permits(Language::Bash, "find . -fprint out"));
+        assert!(!allowlist.permits(Language::Bash, "find . -fls out"));
+        assert!(!allowlist.permits(Language::Bash, "file -C -m magic"));
+
+        assert!(allowlist.permits(
+            Language::Python,
+            "import math, json\nfrom collections import Counter\nprint(math.pi)"
+        ));
+        assert!(!allowlist.permits(Language::Python, "import os\nprint(os.listdir())"));
+        assert!(!allowlist.permits(Language::Python, "print(open('x').read())"));
+        assert!(!allowlist.permits(Language::Python, "x = 1; import os; os.remove('x')"));
+        assert!(!allowlist.permits(
+            Language::Python,
+            "if True: import shutil\nshutil.rmtree('.')"
+        ));
+        assert!(!allowlist.permits(Language::Python, "import math; import os"));
+        assert!(allowlist.permits(Language::Python, "import math as m\nprint(m.pi)"));
+        assert!(!allowlist.permits(Language::Python, "open ('/home/u/.bashrc','w').write('x')"));
+        assert!(!allowlist.permits(Language::Python, "f = open\nf('x', 'w').write('x')"));
+        assert!(!allowlist.permits(
+            Language::Python,
+            "import typing\ntyping.sys.modules['os'].remove('x')"
+        ));
+        assert!(!allowlist.permits(
+            Language::Python,
+            "import collections\ncollections . _sys.modules['os'].remove('x')"
+        ));
+        assert!(!allowlist.permits(
+            Language::Python,
+            "print(().__class__.__bases__[0].__subclasses__())"
+        ));
+        assert!(allowlist.permits(
+            Language::Python,
+            "import json\nif __name__ == '__main__':\n    print(json.dumps([1]))"
+        ));
+
+        assert!(allowlist.permits(Language::Node, "const util = require('util');"));
+        assert!(!allowlist.permits(Language::Node, "const fs = require('fs');"));
+        assert!(!allowlist.permits(Language::Node, "process.binding('spawn_sync');"));
+        assert!(!allowlist.permits(Language::Node, "module.require('fs');"));
+        assert!(allowlist.permits(Language::Node, "const util = require ( 'node:util' );"));
+        assert!(allowlist.permits(
+            Language::Node,
+            "import { format } from 'util';\nconsole.log(Array.from([1]));"
+        ));
+        assert!(!allowlist.permits(Language::Node, "require ('child_process').execSync('ls');"));
+        assert!(!allowlist.permits(Language::Node, "const r = require; r('fs');"));
+        assert!(!allowlist.permits(Language::Node, "Function ('return this')();"));
+        assert!(!allowlist.permits(
+            Language::Node,
+            "[].constructor.constructor('return this')();"
+        ));
+        assert!(!allowlist.permits(Language::Node, "arguments[1]('fs');"));
+        assert!(!allowlist.permits(Language::Node, "import fs from 'fs';"));
+        assert!(!allowlist.permits(Language::Node, "import('fs').then(console.log);"));
+
+        assert!(allowlist.permits(
+            Language::Rust,
+            "use std::collections::HashMap;\nfn main() {}"
+        ));
+        assert!(!allowlist.permits(Language::Rust, "use std::fs;\nfn main() {}"));
+        assert!(!allowlist.permits(
+            Language::Rust,
+            "use std as s;\nfn main() { s::fs::remove_file(\"x\").ok(); }"
+        ));
+        assert!(!allowlist.permits(
+            Language::Rust,
+            "fn main() { std :: fs::remove_file(\"x\").ok(); }"
+        ));
+        assert!(allowlist.permits(
+            Language::Rust,
+            "fn main() { let stdout = 1; println!(\"{}\", stdout); }"
+        ));
+    }
+
+    #[test]
+    fn test_policy_decisions_are_audited() {
+        let dir = ScratchDir::new().unwrap();
+        let log = dir.path().join("audit.jsonl");
+
+        let policy = ApprovalPolicy::new()
+            .with_allowlist(Allowlist::default())
+            .with_approver(|_: Language, code: &str| Ok(code.contains("print")))
+            .with_audit_log(&log);
+
+        assert_eq!(
+            policy.check(Language::Bash, "ls | wc -l").unwrap(),
+            Decision::Approved
+        );
+        assert_eq!(
+            policy
+                .check(Language::Python, "import os\nprint(1)")
+                .unwrap(),
+            Decision::Approved
+        );
+        assert_eq!(
+            policy.check(Language::Python, "import os").unwrap(),
+            Decision::Denied("you declined to run it".to_string())
+        );
+        assert_eq!(
+            policy.check(Language::Bash, "rm -rf /").unwrap(),
+            Decision::Denied("it uses `rm -r`, which deletes files recursively".to_string())
+        );
+        assert_eq!(
+            policy
+                .with_dry_run(true)
+                .check(Language::Bash, "ls")
+                .unwrap(),
+            Decision::DryRun
+        );
+
+        let records: Vec<AuditRecord> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let decided_by: Vec<&str> = records.iter().map(|r| r.decided_by.as_str()).collect();
+        assert_eq!(
+            decided_by,
+            vec!["allowlist", "user", "user", "deny_list", "dry_run"]
+        );
+        assert_eq!(records[3].decision, "denied");
+        assert_eq!(records[3].code, "rm -rf /");
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let policy = ApprovalPolicy::new().with_approver(|_: Language, _: &str| Ok(true));
+        let denied = |language, code| {
+            matches!(
+                policy.check(language, code).unwrap(),
+                Decision::Denied(reason) if reason.starts_with("it uses")
+            )
+        };
+
+        assert!(denied(Language::Bash, "rm -r -f ~/project"));
+        assert!(denied(Language::Bash, "rm -Rf ~/project"));
+        assert!(denied(Language::Bash, "rm --recursive --force ~/project"));
+        assert!(denied(Language::Bash, "/usr/bin/curl -s example.com"));
+        assert!(denied(Language::Bash, "chmod --rec 777 ."));
+        assert!(denied(
+            Language::Python,
+            "import subprocess\nsubprocess.run(['rm', '-r', 'x'])"
+        ));
+        assert!(denied(
+            Language::Python,
+            "from shutil import rmtree\nrmtree('x')"
+        ));
+        assert!(denied(
+            Language::Python,
+            "import shutil as s; s.rmtree('x')"
+        ));
+        assert!(denied(Language::Python, "from http import client"));
+        assert!(denied(Language::Python, "import json, urllib.request as r"));
+        assert!(denied(Language::Python, "import socket"));
+        assert!(denied(Language::Python, "import ftplib"));
+        assert!(denied(Language::Python, "s = __import__('socket')"));
+        assert!(denied(Language::Node, "require('node:https').get('x');"));
+        assert!(denied(Language::Node, "import { get } from \"http\";"));
+        assert!(denied(Language::Node, "fetch ('https://example.com');"));
+        assert!(denied(
+            Language::Rust,
+            "use std::{io, net::TcpStream};\nfn main() {}"
+        ));
+
+        assert!(!denied(Language::Bash, "rm -f out.txt"));
+        assert!(!denied(Language::Bash, "ls -R | wc -l"));
+        assert!(!denied(Language::Python, "import os\nos.remove('out.txt')"));
+        assert!(!denied(Language::Python, "def fetch(url):\n    return url"));
+        assert!(!denied(Language::Node, "const add = (a, b) => a + b;"));
+    }
+
+    #[test]
+    fn test_denied_without_approver() {
+        let decision = ApprovalPolicy::new()
+            .check(Language::Python, "print(1)")
+            .unwrap();
+        assert!(matches!(decision, Decision::Denied(_)));
+    }
+}
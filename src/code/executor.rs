@@ -0,0 +1,656 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use super::Language;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_CPU_SECONDS: u64 = 10;
+pub const DEFAULT_MEMORY_BYTES: u64 = 512 * 1024 * 1024;
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+const OUTPUT_GRACE: Duration = Duration::from_millis(200);
+
+const RUST_MANIFEST: &str = "[package]
+name = \"main\"
+version = \"0.1.0\"
+edition = \"2021\"
+
+[dependencies]
+";
+
+/// Resource limits for a single run. `None` leaves a limit unset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// Wall-clock time before the run is killed.
+    pub timeout: Duration,
+    /// CPU time (`RLIMIT_CPU`).
+    pub cpu_seconds: Option<u64>,
+    /// Address space (`RLIMIT_AS`). Runtimes that reserve large address ranges up
+    /// front, like node, need this unset.
+    pub memory_bytes: Option<u64>,
+    /// Stdout and stderr are each cut off after this many bytes.
+    pub max_output_bytes: usize,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            cpu_seconds: Some(DEFAULT_CPU_SECONDS),
+            memory_bytes: Some(DEFAULT_MEMORY_BYTES),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+}
+
+/// What happened when code was run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExecutionResult {
+    pub stdout: String,
+    pub stderr: String,
+    /// The exit code, if the process exited normally.
+    pub exit_code: Option<i32>,
+    /// The signal that killed the process, on Unix.
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub duration: Duration,
+}
+
+impl ExecutionResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0) && !self.timed_out
+    }
+}
+
+impl Display for ExecutionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stdout = self.stdout.trim();
+        let stderr = self.stderr.trim();
+
+        if self.timed_out {
+            write!(
+                f,
+                "The code timed out after {:.1}s.",
+                self.duration.as_secs_f32()
+            )?;
+        } else if !self.success() {
+            match (self.exit_code, self.signal) {
+                (Some(code), _) => write!(f, "The code failed with exit code {}.", code)?,
+                (None, Some(signal)) => write!(f, "The code was killed by signal {}.", signal)?,
+                (None, None) => write!(f, "The code failed.")?,
+            }
+        } else if stdout.is_empty() {
+            return write!(f, "No output. Could be an issue with the code.");
+        } else {
+            return write!(f, "{}", stdout);
+        }
+
+        if !stdout.is_empty() {
+            write!(f, "\nOutput:\n{}", stdout)?;
+        }
+        if !stderr.is_empty() {
+            write!(f, "\nErrors:\n{}", stderr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs untrusted code in a child process with resource limits and a minimal
+/// environment.
+///
+/// The code is written to `file_name` in a fresh temporary directory and, unless
+/// `without_file_argument`, its full path is passed as the last argument to
+/// `program`. The directory is removed when the run finishes. Each run gets its own
+/// directory, so runs from different sessions don't interfere. The code runs in that
+/// directory with `HOME` pointing at it, so it doesn't touch the user's files unless
+/// `with_working_dir` sets another working directory.
+#[derive(Debug, Clone)]
+pub struct CodeExecutor {
+    program: String,
+    args: Vec<String>,
+    file_name: String,
+    file_argument: bool,
+    files: Vec<(String, String)>,
+    limits: ExecutionLimits,
+    env: Vec<(String, String)>,
+    isolate_network: bool,
+    working_dir: Option<PathBuf>,
+}
+
+impl CodeExecutor {
+    pub fn new(program: &str, file_name: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            file_name: file_name.to_string(),
+            file_argument: true,
+            files: Vec::new(),
+            limits: ExecutionLimits::default(),
+            env: Vec::new(),
+            isolate_network: false,
+            working_dir: None,
+        }
+    }
+
+    pub fn python() -> Self {
+        Self::new("python3", "main.py")
+    }
+
+    pub fn bash() -> Self {
+        Self::new("bash", "main.sh")
+    }
+
+    /// Node reserves a large address space up front, so it runs without a memory
+    /// limit.
+    pub fn node() -> Self {
+        Self::new("node", "main.js").with_memory_limit(None)
+    }
+
+    /// Builds and runs the code as `src/main.rs` of a scratch cargo project, offline
+    /// and without dependencies. Compiling takes longer and needs more memory than
+    /// the defaults allow, and cargo needs the toolchain from the user's environment.
+    /// Build output stays in the run's directory.
+    pub fn rust() -> Self {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let cargo_home = std::env::var("CARGO_HOME").unwrap_or_else(|_| format!("{}/.cargo", home));
+        let rustup_home =
+            std::env::var("RUSTUP_HOME").unwrap_or_else(|_| format!("{}/.rustup", home));
+        let path = std::env::var("PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+
+        Self::new("cargo", "src/main.rs")
+            .with_args(&[
+                "run",
+                "--quiet",
+                "--offline",
+                "--manifest-path",
+                "{dir}/Cargo.toml",
+            ])
+            .without_file_argument()
+            .with_file("Cargo.toml", RUST_MANIFEST)
+            .with_timeout(Duration::from_secs(120))
+            .with_cpu_limit(Some(120))
+            .with_memory_limit(None)
+            .with_env("PATH", &path)
+            .with_env("CARGO_HOME", &cargo_home)
+            .with_env("RUSTUP_HOME", &rustup_home)
+    }
+
+    pub fn for_language(language: Language) -> Self {
+        match language {
+            Language::Bash => Self::bash(),
+            Language::Python => Self::python(),
+            Language::Node => Self::node(),
+            Language::Rust => Self::rust(),
+        }
+    }
+
+    /// Arguments passed to `program` before the file name. `{dir}` in an argument is
+    /// replaced with the run's directory.
+    pub fn with_args(mut self, args: &[&str]) -> Self {
+        self.args = args.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// Don't pass the code's file name to `program`.
+    pub fn without_file_argument(mut self) -> Self {
+        self.file_argument = false;
+        self
+    }
+
+    /// Writes another file, such as a manifest, into the run's directory.
+    pub fn with_file(mut self, name: &str, contents: &str) -> Self {
+        self.files.push((name.to_string(), contents.to_string()));
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.timeout = timeout;
+        self
+    }
+
+    pub fn with_cpu_limit(mut self, seconds: Option<u64>) -> Self {
+        self.limits.cpu_seconds = seconds;
+        self
+    }
+
+    pub fn with_memory_limit(mut self, bytes: Option<u64>) -> Self {
+        self.limits.memory_bytes = bytes;
+        self
+    }
+
+    /// Where the code runs. Defaults to the run's directory.
+    pub fn with_working_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    /// Adds a variable to the child's environment, which otherwise only has `PATH`,
+    /// `LANG`, and `HOME` and `TMPDIR` set to the run's directory.
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Runs the code in new user and network namespaces, so it has no network
+    /// access. Linux only; runs fail if the kernel doesn't allow unprivileged user
+    /// namespaces.
+    pub fn with_network_isolation(mut self, isolate: bool) -> Self {
+        self.isolate_network = isolate;
+        self
+    }
+
+    pub fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    /// Writes `code` to a fresh directory and runs it.
+    pub fn execute(&self, code: &str) -> Result<ExecutionResult, Box<dyn Error>> {
+        let dir = ScratchDir::new()?;
+
+        let files = self
+            .files
+            .iter()
+            .map(|(name, contents)| (name, contents.as_str()));
+        for (name, contents) in files.chain([(&self.file_name, code)]) {
+            let path = dir.path().join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+
+        let dir_name = dir.path().to_string_lossy();
+        let mut command = Command::new(&self.program);
+        command.args(self.args.iter().map(|arg| arg.replace("{dir}", &dir_name)));
+        if self.file_argument {
+            command.arg(dir.path().join(&self.file_name));
+        }
+
+        self.run(&mut command, dir.path())
+    }
+
+    /// Runs `command` under this executor's limits and environment, with `dir` as its
+    /// temporary directory.
+    pub fn run(
+        &self,
+        command: &mut Command,
+        dir: &Path,
+    ) -> Result<ExecutionResult, Box<dyn Error>> {
+        command
+            .current_dir(self.working_dir.as_deref().unwrap_or(dir))
+            .env_clear()
+            .env("PATH", DEFAULT_PATH)
+            .env("HOME", dir)
+            .env("TMPDIR", dir)
+            .env("LANG", "C.UTF-8")
+            .envs(self.env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        self.restrict(command)?;
+
+        let start = Instant::now();
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("Unable to run {}: {}", self.program, err))?;
+
+        let max_output = self.limits.max_output_bytes;
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| CappedReader::spawn(out, max_output));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| CappedReader::spawn(err, max_output));
+
+        let deadline = start + self.limits.timeout;
+        let (status, timed_out) = wait_with_timeout(&mut child, deadline)?;
+        let duration = start.elapsed();
+
+        // Output written just before the exit is still in the pipe, so the readers
+        // get a moment past the deadline to drain it.
+        let deadline = deadline.max(Instant::now() + OUTPUT_GRACE);
+        let collect = |reader: Option<CappedReader>| {
+            reader
+                .map(|reader| reader.finish(deadline))
+                .unwrap_or_default()
+        };
+
+        Ok(ExecutionResult {
+            stdout: collect(stdout),
+            stderr: collect(stderr),
+            exit_code: status.code(),
+            signal: exit_signal(&status),
+            timed_out,
+            duration,
+        })
+    }
+
+    #[cfg(unix)]
+    fn restrict(&self, command: &mut Command) -> Result<(), Box<dyn Error>> {
+        use std::os::unix::process::CommandExt;
+
+        let limits = self.limits.clone();
+        let isolate_network = self.isolate_network;
+
+        if isolate_network && !cfg!(target_os = "linux") {
+            return Err("Network isolation is only supported on Linux".into());
+        }
+
+        // Runs in the child between fork and exec, so it only makes raw syscalls.
+        unsafe {
+            command.pre_exec(move || {
+                // A process group of its own, so a timeout kills any children too.
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(seconds) = limits.cpu_seconds {
+                    // SIGXCPU at the soft limit, SIGKILL a second later.
+                    set_limit(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+                }
+                if let Some(bytes) = limits.memory_bytes {
+                    set_limit(libc::RLIMIT_AS, bytes, bytes)?;
+                }
+                #[cfg(target_os = "linux")]
+                if isolate_network && libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn restrict(&self, _command: &mut Command) -> Result<(), Box<dyn Error>> {
+        match self.isolate_network {
+            true => Err("Network isolation is only supported on Linux".into()),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_limit(resource: Resource, soft: u64, hard: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+
+    match unsafe { libc::setrlimit(resource, &limit) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Waits for `child`, killing it at the deadline. On Unix its whole process group is
+/// killed once it exits either way, so nothing it started in the background outlives
+/// the run.
+fn wait_with_timeout(
+    child: &mut Child,
+    deadline: Instant,
+) -> Result<(ExitStatus, bool), Box<dyn Error>> {
+    let (status, timed_out) = loop {
+        if let Some(status) = child.try_wait()? {
+            break (status, false);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            break (child.wait()?, true);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    Ok((status, timed_out))
+}
+
+/// Reads a pipe to the end on a separate thread, keeping the first `max_bytes`.
+struct CappedReader {
+    thread: thread::JoinHandle<()>,
+    output: Arc<Mutex<(Vec<u8>, bool)>>,
+}
+
+impl CappedReader {
+    fn spawn<R: Read + Send + 'static>(mut reader: R, max_bytes: usize) -> Self {
+        let output = Arc::new(Mutex::new((Vec::new(), false)));
+        let shared = output.clone();
+
+        let thread = thread::spawn(move || {
+            let mut buffer = [0; 8192];
+            while let Ok(read) = reader.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let (kept, truncated) = &mut *shared.lock().unwrap();
+                let room = max_bytes.saturating_sub(kept.len());
+                kept.extend_from_slice(&buffer[..read.min(room)]);
+                *truncated |= read > room;
+            }
+        });
+
+        Self { thread, output }
+    }
+
+    /// What was read by the time the pipe closed, or by `deadline` if something that
+    /// escaped the process group still holds it open. The thread is left to finish
+    /// on its own then.
+    fn finish(self, deadline: Instant) -> String {
+        while !self.thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (kept, truncated) = &*self.output.lock().unwrap();
+        let mut output = String::from_utf8_lossy(kept).into_owned();
+        if *truncated {
+            output.push_str("\n[output truncated]");
+        }
+        output
+    }
+}
+
+/// Whether `CodeExecutor::with_network_isolation` works here: on Linux, when the
+/// kernel allows unprivileged user namespaces. Checked once, by running `true`.
+pub fn network_isolation_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+
+    *SUPPORTED.get_or_init(|| {
+        cfg!(target_os = "linux")
+            && CodeExecutor::new("true", "unused")
+                .without_file_argument()
+                .with_network_isolation(true)
+                .execute("")
+                .is_ok_and(|result| result.success())
+    })
+}
+
+/// A private temporary directory, removed on drop.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn new() -> std::io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or_default();
+        let name = format!(
+            "assistant-run-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos
+        );
+
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir(&path)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o700))?;
+        }
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh() -> CodeExecutor {
+        CodeExecutor::new("sh", "script.sh")
+    }
+
+    #[test]
+    fn test_captures_output_and_status() {
+        let result = sh().execute("echo out; echo err >&2; exit 3").unwrap();
+
+        assert_eq!(result.stdout, "out\n");
+        assert_eq!(result.stderr, "err\n");
+        assert_eq!(result.exit_code, Some(3));
+        assert!(!result.success());
+        assert_eq!(
+            result.to_string(),
+            "The code failed with exit code 3.\nOutput:\nout\nErrors:\nerr"
+        );
+    }
+
+    #[test]
+    fn test_scratch_dir_and_environment() {
+        let result = sh()
+            .with_env("GREETING", "ahoy")
+            .execute("dirname \"$0\"; pwd; echo \"$HOME\"; env | cut -d= -f1 | sort | tr '\\n' ' '")
+            .unwrap();
+
+        let mut lines = result.stdout.lines();
+        let dir = lines.next().unwrap();
+        assert!(dir.contains("assistant-run-"));
+        assert!(!Path::new(dir).exists());
+
+        assert_eq!(lines.next().unwrap(), dir);
+        assert_eq!(lines.next().unwrap(), dir);
+
+        let vars: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+        assert!(vars.contains(&"GREETING"));
+        assert!(!vars.iter().any(|var| var.starts_with("CARGO")));
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let dir = ScratchDir::new().unwrap();
+        fs::write(dir.path().join("data.txt"), "42").unwrap();
+
+        let result = sh()
+            .with_working_dir(dir.path())
+            .execute("cat data.txt")
+            .unwrap();
+        assert_eq!(result.stdout, "42");
+    }
+
+    #[test]
+    fn test_language_backends() {
+        let bash = CodeExecutor::for_language(Language::Bash)
+            .execute("basename \"$0\"; echo $((6 * 7))")
+            .unwrap();
+        assert_eq!(bash.stdout, "main.sh\n42\n");
+    }
+
+    /// Compiles a crate, which is slow and needs cargo on the `PATH`.
+    #[test]
+    #[ignore]
+    fn test_rust_backend() {
+        let rust = CodeExecutor::for_language(Language::Rust)
+            .execute("fn main() {\n    println!(\"{}\", (1..=10).sum::<u32>());\n}\n")
+            .unwrap();
+        assert!(rust.success(), "{}", rust);
+        assert_eq!(rust.stdout, "55\n");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let result = sh()
+            .with_timeout(Duration::from_millis(200))
+            .execute("echo started; sleep 5")
+            .unwrap();
+
+        assert!(result.timed_out);
+        assert!(result.duration < Duration::from_secs(2));
+        assert_eq!(result.stdout, "started\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_background_processes_are_killed() {
+        let start = Instant::now();
+        let result = sh()
+            .with_timeout(Duration::from_secs(5))
+            .execute("sleep 30 &\necho started")
+            .unwrap();
+
+        assert!(!result.timed_out);
+        assert_eq!(result.stdout, "started\n");
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_limit() {
+        let result = sh()
+            .with_cpu_limit(Some(1))
+            .execute("while :; do :; done")
+            .unwrap();
+
+        assert!(!result.timed_out);
+        assert_eq!(result.signal, Some(libc::SIGXCPU));
+    }
+}
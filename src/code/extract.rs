@@ -0,0 +1,259 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::middleware::{ConversationContext, Middleware};
+use crate::response::{keys, Response};
+
+/// A fenced code block from a model's reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The canonical language of the block's tag, if it had one.
+    pub language: Option<String>,
+    pub code: String,
+}
+
+/// The canonical name for a language tag, e.g. `py` and `python3` are both `python`.
+pub fn canonical_language(tag: &str) -> String {
+    let tag = tag.trim().to_lowercase();
+    let canonical = match tag.as_str() {
+        "py" | "python3" => "python",
+        "sh" | "shell" | "zsh" | "console" => "bash",
+        "js" | "node" | "nodejs" => "javascript",
+        "rs" => "rust",
+        "yml" => "yaml",
+        other => other,
+    };
+    canonical.to_string()
+}
+
+/// Every fenced block in `text`, in order. A block left open at the end of the text
+/// runs to the end. Only the first word of the info string is used as the tag.
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("```") {
+        let after = &rest[start + 3..];
+        let Some(newline) = after.find('\n') else {
+            break;
+        };
+        let language = after[..newline]
+            .split_whitespace()
+            .next()
+            .map(canonical_language);
+        let body = &after[newline + 1..];
+
+        let (code, next) = match body.find("```") {
+            Some(end) => (&body[..end], &body[end + 3..]),
+            None => (body, ""),
+        };
+        blocks.push(CodeBlock {
+            language,
+            code: code.trim_end().to_string(),
+        });
+        rest = next;
+    }
+
+    blocks
+}
+
+/// Which matching block(s) `CodeExtractor` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockSelection {
+    #[default]
+    First,
+    Last,
+    Longest,
+    /// Every matching block, joined by blank lines.
+    Concatenate,
+}
+
+/// Returned when a reply contains no code to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoCodeFound {
+    pub language: Option<String>,
+}
+
+impl Display for NoCodeFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.language {
+            Some(language) => write!(f, "The response contained no {} code", language),
+            None => write!(f, "The response contained no code"),
+        }
+    }
+}
+
+impl Error for NoCodeFound {}
+
+/// Pulls the runnable code out of a model's reply.
+///
+/// Blocks tagged with another language are skipped; untagged blocks match any
+/// language. A reply without any fences is taken to be code as a whole, unless
+/// `with_unfenced(false)`. Also a `Middleware` that replaces a chatbot's response
+/// with the extracted code.
+#[derive(Debug, Clone)]
+pub struct CodeExtractor {
+    language: Option<String>,
+    selection: BlockSelection,
+    unfenced: bool,
+}
+
+impl Default for CodeExtractor {
+    fn default() -> Self {
+        Self {
+            language: None,
+            selection: BlockSelection::default(),
+            unfenced: true,
+        }
+    }
+}
+
+impl CodeExtractor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn for_language(language: &str) -> Self {
+        Self {
+            language: Some(canonical_language(language)),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_selection(mut self, selection: BlockSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    pub fn with_unfenced(mut self, unfenced: bool) -> Self {
+        self.unfenced = unfenced;
+        self
+    }
+
+    /// The blocks in `text` this extractor accepts.
+    pub fn blocks(&self, text: &str) -> Vec<CodeBlock> {
+        code_blocks(text)
+            .into_iter()
+            .filter(|block| !block.code.trim().is_empty())
+            .filter(|block| match (&self.language, &block.language) {
+                (Some(wanted), Some(language)) => wanted == language,
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// The selected code block from `text`.
+    pub fn extract_block(&self, text: &str) -> Result<CodeBlock, NoCodeFound> {
+        let no_code = || NoCodeFound {
+            language: self.language.clone(),
+        };
+
+        if !text.contains("```") {
+            return match self.unfenced && !text.trim().is_empty() {
+                true => Ok(CodeBlock {
+                    language: self.language.clone(),
+                    code: text.trim().to_string(),
+                }),
+                false => Err(no_code()),
+            };
+        }
+
+        let mut blocks = self.blocks(text);
+        let block = match self.selection {
+            BlockSelection::First => blocks.into_iter().next(),
+            BlockSelection::Last => blocks.pop(),
+            BlockSelection::Longest => blocks.into_iter().max_by_key(|block| block.code.len()),
+            BlockSelection::Concatenate => match blocks.is_empty() {
+                true => None,
+                false => Some(CodeBlock {
+                    language: blocks.iter().find_map(|block| block.language.clone()),
+                    code: blocks
+                        .iter()
+                        .map(|block| block.code.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n\n"),
+                }),
+            },
+        };
+
+        block.ok_or_else(no_code)
+    }
+
+    /// The selected code from `text`.
+    pub fn extract(&self, text: &str) -> Result<String, NoCodeFound> {
+        Ok(self.extract_block(text)?.code)
+    }
+}
+
+impl Middleware for CodeExtractor {
+    fn after(
+        &mut self,
+        context: &ConversationContext,
+        input: &str,
+        output: Response,
+    ) -> Result<Response, Box<dyn Error>> {
+        let _ = (context, input);
+        let block = self.extract_block(&output.text)?;
+
+        let mut response = Response {
+            text: block.code,
+            ..output
+        };
+        if let Some(language) = block.language {
+            response.set_metadata(keys::CODE_LANGUAGE, language);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &str = "Here's a script:\n\n```python\nimport math\n```\n\nRun it with:\n\n```bash\npython3 script.py\n```\n\nAnd then:\n\n```py\nprint(math.pi)\n```\n";
+
+    #[test]
+    fn test_code_blocks() {
+        let blocks = code_blocks(REPLY);
+
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].language.as_deref(), Some("python"));
+        assert_eq!(blocks[0].code, "import math");
+        assert_eq!(blocks[1].language.as_deref(), Some("bash"));
+        assert_eq!(blocks[2].language.as_deref(), Some("python"));
+
+        let unterminated = code_blocks("```\nprint(1)\n");
+        assert_eq!(unterminated[0].language, None);
+        assert_eq!(unterminated[0].code, "print(1)");
+    }
+
+    #[test]
+    fn test_extract() {
+        let python = CodeExtractor::for_language("python3");
+
+        assert_eq!(python.extract(REPLY).unwrap(), "import math");
+        assert_eq!(
+            python
+                .clone()
+                .with_selection(BlockSelection::Concatenate)
+                .extract(REPLY)
+                .unwrap(),
+            "import math\n\nprint(math.pi)"
+        );
+        assert_eq!(
+            CodeExtractor::for_language("sh").extract(REPLY).unwrap(),
+            "python3 script.py"
+        );
+
+        assert_eq!(python.extract("  print(42)\n").unwrap(), "print(42)");
+        assert!(python
+            .clone()
+            .with_unfenced(false)
+            .extract("print(42)")
+            .is_err());
+
+        let err = python.extract("Sorry:\n```text\nI can't\n```").unwrap_err();
+        assert_eq!(err.to_string(), "The response contained no python code");
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use serde::Serialize;
+
+use super::approval::{ApprovalPolicy, Decision};
+use super::executor::{CodeExecutor, ExecutionResult};
+use super::extract::{CodeExtractor, NoCodeFound};
+use super::Language;
+use crate::model_traits::{CompletionModel, Responder};
+use crate::response::{keys, Attachment, Response};
+use crate::template::{PromptTemplate, TemplateRegistry};
+
+const BASH_PROMPT: &str = "Write a bash script that does the following task. Prefer a short one-liner using standard Unix tools, and print the result.
+Task: {task}
+```bash
+";
+
+const PYTHON_PROMPT: &str =
+    "Write a python script to solve the following problem. Print the result.
+Problem: {task}
+```python
+";
+
+const NODE_PROMPT: &str = "Write a Node.js script to solve the following problem, using only built-in modules. Print the result with console.log.
+Problem: {task}
+```javascript
+";
+
+const RUST_PROMPT: &str = "Write a complete Rust program with a main function that solves the following problem, using only the standard library. Print the result.
+Problem: {task}
+```rust
+";
+
+const REPAIR_PROMPT: &str =
+    "This {language} code was written to solve the following problem, but it failed.
+Problem: {task}
+```{tag}
+{code}
+```
+Result:
+{error}
+
+Fix the code so it solves the problem.
+```{tag}
+";
+
+const SYNTHESIS_PROMPT: &str = "A user asked a question, and this {language} code was run to answer it.
+Question: {task}
+Code:
+```{tag}
+{code}
+```
+Result:
+{output}
+
+Answer the user's question in one or two sentences, based on the result. If the code failed, briefly say what went wrong.
+Answer:";
+
+pub const DEFAULT_MAX_REPAIRS: usize = 2;
+
+/// One run (or attempted run) of generated code.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attempt {
+    pub language: Language,
+    pub code: String,
+    pub decision: Decision,
+    /// `None` if the code wasn't approved to run.
+    pub result: Option<ExecutionResult>,
+    /// Why no fix could be made for this attempt, if asking for one failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repair_error: Option<String>,
+}
+
+impl Attempt {
+    pub fn failed(&self) -> bool {
+        self.result.as_ref().is_some_and(|result| !result.success())
+    }
+}
+
+/// Every attempt at solving a task, in order. The last one is the outcome.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Solution {
+    pub attempts: Vec<Attempt>,
+}
+
+impl Solution {
+    pub fn last(&self) -> &Attempt {
+        self.attempts
+            .last()
+            .expect("A solution has at least one attempt")
+    }
+}
+
+/// The prompt each language starts with. Prompts open a fenced block, so the model
+/// continues with code and stops at the closing fence.
+pub fn default_prompt(language: Language) -> &'static str {
+    match language {
+        Language::Bash => BASH_PROMPT,
+        Language::Python => PYTHON_PROMPT,
+        Language::Node => NODE_PROMPT,
+        Language::Rust => RUST_PROMPT,
+    }
+}
+
+/// Solves tasks by generating code and running it.
+///
+/// The language comes from the task (see `Language::from_task`), falling back to a
+/// default; each language has its own prompt template, with the task in `{task}`.
+/// If the model answers with a block tagged as another supported language, it runs
+/// in that language instead. Code that fails is sent back to the model with its
+/// error output for a fix, up to `with_max_repairs` times.
+pub struct CodeExecutionResponder<M: CompletionModel> {
+    model: M,
+    prompts: HashMap<Language, PromptTemplate>,
+    repair_prompt: PromptTemplate,
+    max_repairs: usize,
+    executors: HashMap<Language, CodeExecutor>,
+    default_language: Language,
+    approval: Option<ApprovalPolicy>,
+    synthesizer: Option<Box<dyn CompletionModel>>,
+    synthesis_prompt: PromptTemplate,
+}
+
+impl<M: CompletionModel> CodeExecutionResponder<M> {
+    pub fn new(model: M) -> Self {
+        let prompts = Language::ALL
+            .iter()
+            .map(|&language| {
+                let template = PromptTemplate::parse(language.tag(), default_prompt(language))
+                    .expect("Default code prompts are valid templates");
+                (language, template)
+            })
+            .collect();
+
+        let executors = Language::ALL
+            .iter()
+            .map(|&language| (language, CodeExecutor::for_language(language)))
+            .collect();
+
+        Self {
+            model,
+            prompts,
+            repair_prompt: PromptTemplate::parse("repair", REPAIR_PROMPT)
+                .expect("The default repair prompt is a valid template"),
+            max_repairs: DEFAULT_MAX_REPAIRS,
+            executors,
+            default_language: Language::Python,
+            approval: None,
+            synthesizer: None,
+            synthesis_prompt: PromptTemplate::parse("synthesis", SYNTHESIS_PROMPT)
+                .expect("The default synthesis prompt is a valid template"),
+        }
+    }
+
+    /// Replaces a language's prompt, failing if it uses variables other than `{task}`.
+    pub fn with_prompt(
+        mut self,
+        language: Language,
+        template: PromptTemplate,
+    ) -> Result<Self, Box<dyn Error>> {
+        let variables = HashSet::from(["task".to_string()]);
+        template.validate(&TemplateRegistry::new(), &variables)?;
+
+        self.prompts.insert(language, template);
+        Ok(self)
+    }
+
+    /// Replaces the prompt used to fix failing code. It can use `{language}`, `{tag}`,
+    /// `{task}`, `{code}` and `{error}`.
+    pub fn with_repair_prompt(mut self, template: PromptTemplate) -> Result<Self, Box<dyn Error>> {
+        let variables = ["language", "tag", "task", "code", "error"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        template.validate(&TemplateRegistry::new(), &variables)?;
+
+        self.repair_prompt = template;
+        Ok(self)
+    }
+
+    /// How many times failing code is sent back for a fix. Defaults to
+    /// `DEFAULT_MAX_REPAIRS`; 0 disables repairs.
+    pub fn with_max_repairs(mut self, max_repairs: usize) -> Self {
+        self.max_repairs = max_repairs;
+        self
+    }
+
+    pub fn with_executor(mut self, language: Language, executor: CodeExecutor) -> Self {
+        self.executors.insert(language, executor);
+        self
+    }
+
+    /// The language used when a task doesn't suggest one. Defaults to Python.
+    pub fn with_default_language(mut self, language: Language) -> Self {
+        self.default_language = language;
+        self
+    }
+
+    /// Checks generated code with `policy` before running it. Without a policy, code
+    /// runs as soon as it's generated.
+    pub fn with_approval(mut self, policy: ApprovalPolicy) -> Self {
+        self.approval = Some(policy);
+        self
+    }
+
+    /// Turns the raw output of the code into a natural-language answer to the
+    /// question with a second model pass. The code and raw output stay available as
+    /// the `code` and `output` attachments.
+    pub fn with_synthesizer<S: CompletionModel + 'static>(mut self, model: S) -> Self {
+        self.synthesizer = Some(Box::new(model));
+        self
+    }
+
+    /// Replaces the answer synthesis prompt. It can use `{language}`, `{tag}`,
+    /// `{task}`, `{code}` and `{output}`.
+    pub fn with_synthesis_prompt(
+        mut self,
+        template: PromptTemplate,
+    ) -> Result<Self, Box<dyn Error>> {
+        let variables = ["language", "tag", "task", "code", "output"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        template.validate(&TemplateRegistry::new(), &variables)?;
+
+        self.synthesis_prompt = template;
+        Ok(self)
+    }
+
+    pub fn language_for(&self, task: &str) -> Language {
+        Language::from_task(task).unwrap_or(self.default_language)
+    }
+
+    /// Generates code for `task`, returning it with the language it should run in.
+    pub fn generate(&self, task: &str) -> Result<(Language, String), Box<dyn Error>> {
+        let language = self.language_for(task);
+        let variables = HashMap::from([("task".to_string(), task.trim().to_string())]);
+        let prompt = self.prompts[&language].render(&variables, &TemplateRegistry::new())?;
+
+        self.complete_code(&prompt, language)
+    }
+
+    /// Asks the model to fix the code from a failed attempt.
+    pub fn repair(
+        &self,
+        task: &str,
+        attempt: &Attempt,
+    ) -> Result<(Language, String), Box<dyn Error>> {
+        let error = match &attempt.result {
+            Some(result) => result.to_string(),
+            None => "The code was not run.".to_string(),
+        };
+        let variables = HashMap::from([
+            ("language".to_string(), attempt.language.to_string()),
+            ("tag".to_string(), attempt.language.tag().to_string()),
+            ("task".to_string(), task.trim().to_string()),
+            ("code".to_string(), attempt.code.clone()),
+            ("error".to_string(), error),
+        ]);
+        let prompt = self
+            .repair_prompt
+            .render(&variables, &TemplateRegistry::new())?;
+
+        self.complete_code(&prompt, attempt.language)
+    }
+
+    fn complete_code(
+        &self,
+        prompt: &str,
+        language: Language,
+    ) -> Result<(Language, String), Box<dyn Error>> {
+        let completion = self
+            .model
+            .complete_with_stop(prompt, &["```".to_string()])?;
+
+        let block = CodeExtractor::new().extract_block(&completion)?;
+        let language = block
+            .language
+            .as_deref()
+            .and_then(Language::from_tag)
+            .unwrap_or(language);
+
+        Ok((language, block.code))
+    }
+
+    /// A natural-language answer to `task` from an attempt that ran, if there is a
+    /// synthesizer.
+    pub fn synthesize(
+        &self,
+        task: &str,
+        attempt: &Attempt,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let (Some(synthesizer), Some(result)) = (&self.synthesizer, &attempt.result) else {
+            return Ok(None);
+        };
+
+        let variables = HashMap::from([
+            ("language".to_string(), attempt.language.to_string()),
+            ("tag".to_string(), attempt.language.tag().to_string()),
+            ("task".to_string(), task.trim().to_string()),
+            ("code".to_string(), attempt.code.clone()),
+            ("output".to_string(), result.to_string()),
+        ]);
+        let prompt = self
+            .synthesis_prompt
+            .render(&variables, &TemplateRegistry::new())?;
+
+        let answer = synthesizer.complete(&prompt)?.trim().to_string();
+        Ok((!answer.is_empty()).then_some(answer))
+    }
+
+    /// Generates code for `task` and runs it if approved, repairing failures.
+    pub fn solve(&self, task: &str) -> Result<Solution, Box<dyn Error>> {
+        let (mut language, mut code) = self.generate(task)?;
+        let mut attempts = Vec::new();
+
+        loop {
+            let decision = match &self.approval {
+                Some(policy) => policy.check(language, &code)?,
+                None => Decision::Approved,
+            };
+            let result = match decision {
+                Decision::Approved => Some(self.run(language, &code)?),
+                _ => None,
+            };
+
+            let attempt = Attempt {
+                language,
+                code,
+                decision,
+                result,
+                repair_error: None,
+            };
+            let retry = attempt.failed() && attempts.len() < self.max_repairs;
+            attempts.push(attempt);
+            if !retry {
+                break;
+            }
+
+            // If no fix comes back, the attempts so far still stand, with the last
+            // failure as the outcome.
+            match self.repair(task, attempts.last().unwrap()) {
+                Ok((next_language, next_code)) => {
+                    language = next_language;
+                    code = next_code;
+                }
+                Err(err) => {
+                    attempts.last_mut().unwrap().repair_error = Some(err.to_string());
+                    break;
+                }
+            }
+        }
+
+        Ok(Solution { attempts })
+    }
+
+    /// Runs `code` with the executor for `language`.
+    pub fn run(&self, language: Language, code: &str) -> Result<ExecutionResult, Box<dyn Error>> {
+        match self.executors.get(&language) {
+            Some(executor) => executor.execute(code),
+            None => Err(format!("No executor for {}", language).into()),
+        }
+    }
+}
+
+impl<M: CompletionModel> Responder for CodeExecutionResponder<M> {
+    /// The response describes the last attempt, as a synthesized answer if there is a
+    /// synthesizer. The code, its raw output and the full attempt history (as JSON)
+    /// are attached as `code`, `output` and `attempts`.
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>> {
+        let solution = match self.solve(input) {
+            Ok(solution) => solution,
+            Err(err) if err.is::<NoCodeFound>() => {
+                return Ok(
+                    Response::new(&format!("{}. Try rephrasing the problem.", err))
+                        .with_metadata(keys::CODE_EXECUTED, false),
+                )
+            }
+            Err(err) => return Err(err),
+        };
+
+        let last = solution.last();
+        let language = last.language;
+        let answer = self.synthesize(input, last).unwrap_or_else(|err| {
+            eprintln!("Unable to write an answer from the code's output: {}", err);
+            None
+        });
+        let text = match (&last.decision, &last.result) {
+            (_, Some(result)) => answer.unwrap_or_else(|| result.to_string()),
+            (Decision::Denied(reason), None) => format!(
+                "I didn't run this code because {}:\n```{}\n{}\n```",
+                reason,
+                language.tag(),
+                last.code
+            ),
+            (_, None) => format!(
+                "Dry run, so I didn't run this code:\n```{}\n{}\n```",
+                language.tag(),
+                last.code
+            ),
+        };
+
+        let mut response = Response::new(&text)
+            .with_metadata(keys::CODE_EXECUTED, last.result.is_some())
+            .with_metadata(keys::CODE_LANGUAGE, language.tag())
+            .with_metadata(keys::ATTEMPTS, solution.attempts.len())
+            .with_attachment(Attachment::new("code", language.mime_type(), &last.code))
+            .with_attachment(Attachment::new(
+                "attempts",
+                "application/json",
+                &serde_json::to_string_pretty(&solution)?,
+            ));
+        if let Some(result) = &last.result {
+            response = response.with_attachment(Attachment::new(
+                "output",
+                "text/plain",
+                &result.to_string(),
+            ));
+            if let Some(code) = result.exit_code {
+                response.set_metadata(keys::EXIT_CODE, code as i64);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Answers in the language the prompt asks for, or in `reply` verbatim if set.
+    struct CodeModel {
+        reply: Option<&'static str>,
+    }
+
+    impl CompletionModel for CodeModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            if let Some(reply) = self.reply {
+                return Ok(reply.to_string());
+            }
+            match prompt.ends_with("```bash\n") {
+                true => Ok("echo hello from bash\n".to_string()),
+                false => Ok("print('hello from python')\n".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_language_selection() {
+        let mut responder = CodeExecutionResponder::new(CodeModel { reply: None });
+
+        let response = responder
+            .respond("count the files in this directory")
+            .unwrap();
+        assert_eq!(response.text, "hello from bash");
+        assert_eq!(
+            response.metadata(keys::CODE_LANGUAGE).unwrap().to_string(),
+            "bash"
+        );
+        assert_eq!(
+            response.attachment("code").unwrap().content,
+            "echo hello from bash"
+        );
+
+        let response = responder.respond("What is 2 + 2?").unwrap();
+        assert_eq!(response.text, "hello from python");
+    }
+
+    #[test]
+    fn test_approval() {
+        let mut responder = CodeExecutionResponder::new(CodeModel {
+            reply: Some("rm -rf ~\n"),
+        })
+        .with_approval(ApprovalPolicy::new().with_approver(|_: Language, _: &str| Ok(true)));
+
+        let response = responder.respond("Tidy up my files").unwrap();
+        assert_eq!(
+            response.text,
+            "I didn't run this code because it uses `rm -r`, which deletes files recursively:\n```bash\nrm -rf ~\n```"
+        );
+        assert_eq!(
+            response.metadata(keys::CODE_EXECUTED).unwrap().to_string(),
+            "false"
+        );
+    }
+
+    /// Writes failing bash until it's shown its own error, then fixes it.
+    struct RepairingModel {
+        prompts: Mutex<Vec<String>>,
+        fixes: bool,
+    }
+
+    impl CompletionModel for RepairingModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            self.prompts.lock().unwrap().push(prompt.to_string());
+            match self.fixes && prompt.contains("Fix the code") {
+                true => Ok("echo fixed\n".to_string()),
+                false => Ok("echo broken >&2; exit 2\n".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_repairs_failing_code() {
+        let responder = CodeExecutionResponder::new(RepairingModel {
+            prompts: Mutex::new(Vec::new()),
+            fixes: true,
+        })
+        .with_default_language(Language::Bash);
+
+        let solution = responder.solve("Say something").unwrap();
+        assert_eq!(solution.attempts.len(), 2);
+        assert_eq!(
+            solution.attempts[0].result.as_ref().unwrap().exit_code,
+            Some(2)
+        );
+        assert_eq!(solution.last().result.as_ref().unwrap().stdout, "fixed\n");
+
+        let prompts = responder.model.prompts.lock().unwrap();
+        assert!(prompts[1].contains("```bash\necho broken >&2; exit 2\n```"));
+        assert!(prompts[1].contains("The code failed with exit code 2.\nErrors:\nbroken"));
+    }
+
+    #[test]
+    fn test_gives_up_after_max_repairs() {
+        let mut responder = CodeExecutionResponder::new(RepairingModel {
+            prompts: Mutex::new(Vec::new()),
+            fixes: false,
+        })
+        .with_default_language(Language::Bash)
+        .with_max_repairs(1);
+
+        let response = responder.respond("Say something").unwrap();
+        assert_eq!(response.metadata(keys::ATTEMPTS).unwrap().to_string(), "2");
+        assert_eq!(response.metadata(keys::EXIT_CODE).unwrap().to_string(), "2");
+
+        let attempts: serde_json::Value =
+            serde_json::from_str(&response.attachment("attempts").unwrap().content).unwrap();
+        assert_eq!(attempts["attempts"].as_array().unwrap().len(), 2);
+    }
+
+    /// Writes failing bash, then can't be reached to fix it.
+    struct UnreachableRepairModel;
+
+    impl CompletionModel for UnreachableRepairModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            match prompt.contains("Fix the code") {
+                true => Err("connection reset".into()),
+                false => Ok("echo broken >&2; exit 2\n".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_repair_error_keeps_attempts() {
+        let responder = CodeExecutionResponder::new(UnreachableRepairModel)
+            .with_default_language(Language::Bash);
+
+        let solution = responder.solve("Say something").unwrap();
+        assert_eq!(solution.attempts.len(), 1);
+        assert_eq!(solution.last().result.as_ref().unwrap().exit_code, Some(2));
+        assert_eq!(
+            solution.last().repair_error.as_deref(),
+            Some("connection reset")
+        );
+    }
+
+    struct FailingModel;
+
+    impl CompletionModel for FailingModel {
+        fn complete(&self, _prompt: &str) -> Result<String, Box<dyn Error>> {
+            Err("model unavailable".into())
+        }
+    }
+
+    #[test]
+    fn test_synthesis_error_falls_back_to_output() {
+        let mut responder = CodeExecutionResponder::new(CodeModel {
+            reply: Some("echo 42\n"),
+        })
+        .with_default_language(Language::Bash)
+        .with_synthesizer(FailingModel);
+
+        let response = responder.respond("What is 6 times 7?").unwrap();
+        assert_eq!(response.text, "42");
+    }
+
+    struct AnswerModel;
+
+    impl CompletionModel for AnswerModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            let question = prompt.split("Question: ").nth(1).unwrap().lines().next();
+            let result = prompt.split("Result:\n").nth(1).unwrap().lines().next();
+            Ok(format!(
+                " The answer to '{}' is {}.",
+                question.unwrap(),
+                result.unwrap()
+            ))
+        }
+    }
+
+    #[test]
+    fn test_answer_synthesis() {
+        let mut responder = CodeExecutionResponder::new(CodeModel {
+            reply: Some("echo 42\n"),
+        })
+        .with_default_language(Language::Bash)
+        .with_synthesizer(AnswerModel);
+
+        let response = responder.respond("What is 6 times 7?").unwrap();
+        assert_eq!(response.text, "The answer to 'What is 6 times 7?' is 42.");
+        assert_eq!(response.attachment("code").unwrap().content, "echo 42");
+        assert_eq!(response.attachment("output").unwrap().content, "42");
+    }
+
+    #[test]
+    fn test_block_tag_overrides_language() {
+        let responder = CodeExecutionResponder::new(CodeModel {
+            reply: Some("Use the shell:\n```sh\necho 4\n```\n"),
+        });
+
+        let (language, code) = responder.generate("What is 2 + 2?").unwrap();
+        assert_eq!(language, Language::Bash);
+        assert_eq!(code, "echo 4");
+    }
+}
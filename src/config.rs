@@ -0,0 +1,6 @@
+use std::path::PathBuf;
+
+/// The directory holding the assistant's configuration, `~/.config/assistant`.
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("assistant"))
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// A single message in a conversation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl Turn {
+    pub fn new(role: Role, content: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            role,
+            content: content.to_string(),
+            timestamp,
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn system(content: &str) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Renders turns as a labelled transcript, e.g.
+///
+/// ```text
+/// User: Hello
+/// Assistant: Ahoy!
+/// ```
+///
+/// System turns are rendered without a label.
+#[derive(Debug, Clone)]
+pub struct TranscriptFormatter {
+    pub user_label: String,
+    pub assistant_label: String,
+}
+
+impl Default for TranscriptFormatter {
+    fn default() -> Self {
+        Self::new("User:", "Assistant:")
+    }
+}
+
+impl TranscriptFormatter {
+    pub fn new(user_label: &str, assistant_label: &str) -> Self {
+        Self {
+            user_label: user_label.to_string(),
+            assistant_label: assistant_label.to_string(),
+        }
+    }
+
+    pub fn render_turn(&self, turn: &Turn) -> String {
+        match turn.role {
+            Role::System => turn.content.clone(),
+            Role::User => format!("{} {}", self.user_label, turn.content),
+            Role::Assistant => format!("{} {}", self.assistant_label, turn.content),
+        }
+    }
+
+    pub fn render(&self, turns: &[Turn]) -> String {
+        turns
+            .iter()
+            .map(|turn| self.render_turn(turn))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// The cue placed at the end of a prompt for the model to continue from.
+    pub fn reply_cue(&self) -> String {
+        self.assistant_label.clone()
+    }
+
+    /// Sequences that stop the model from writing the user's next turn itself.
+    pub fn stop_sequences(&self) -> Vec<String> {
+        vec![format!("\n{}", self.user_label)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_transcript() {
+        let formatter = TranscriptFormatter::default();
+        let turns = vec![
+            Turn::system("Be nice."),
+            Turn::user("Hello"),
+            Turn::assistant("Hi there"),
+        ];
+
+        assert_eq!(
+            formatter.render(&turns),
+            "Be nice.\nUser: Hello\nAssistant: Hi there"
+        );
+        assert_eq!(formatter.stop_sequences(), vec!["\nUser:"]);
+    }
+}
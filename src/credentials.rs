@@ -0,0 +1,71 @@
+pub mod providers;
+pub mod rotation;
+
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::config::config_dir;
+
+use self::providers::{ChainProvider, ConfigFileProvider, EnvVarProvider, KeyFileProvider};
+use self::rotation::{KeyRotator, RotationStrategy};
+
+pub const DEFAULT_KEY_VAR: &str = "OPENAI_KEY";
+
+/// A source of API keys.
+///
+/// Providers are shared between clients, so implementations that track state
+/// (such as key rotation) must use interior mutability.
+pub trait CredentialProvider: Send + Sync {
+    /// All keys this provider knows about.
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// The key to use for the next request.
+    fn api_key(&self) -> Result<String, Box<dyn Error>> {
+        self.api_keys()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "Credential provider returned no keys".into())
+    }
+
+    /// Called after a request with the number of tokens it consumed.
+    fn record_usage(&self, _key: &str, _tokens: u32) {}
+}
+
+/// A single, fixed key.
+pub struct StaticKey(pub String);
+
+impl CredentialProvider for StaticKey {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(vec![self.0.clone()])
+    }
+}
+
+/// The default provider chain: the `OPENAI_KEY` environment variable, then
+/// `~/.config/assistant/openai_key`, then `~/.config/assistant/config.json`.
+pub fn default_provider_chain() -> ChainProvider {
+    let mut chain = ChainProvider::new().with(EnvVarProvider::new(DEFAULT_KEY_VAR));
+
+    if let Some(dir) = config_dir() {
+        chain = chain
+            .with(KeyFileProvider::new(dir.join("openai_key")))
+            .with(ConfigFileProvider::new(dir.join("config.json")));
+    }
+
+    chain
+}
+
+/// Resolves keys from `provider` once and rotates between them with `strategy`.
+///
+/// Returns an error naming every source that was tried if no key is found.
+pub fn resolve_credentials<P: CredentialProvider + ?Sized>(
+    provider: &P,
+    strategy: RotationStrategy,
+) -> Result<Arc<dyn CredentialProvider>, Box<dyn Error>> {
+    let keys = provider.api_keys()?;
+    Ok(Arc::new(KeyRotator::new(keys, strategy)?))
+}
+
+/// Keys from the default provider chain, rotated round-robin.
+pub fn default_credentials() -> Result<Arc<dyn CredentialProvider>, Box<dyn Error>> {
+    resolve_credentials(&default_provider_chain(), RotationStrategy::RoundRobin)
+}
@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use super::CredentialProvider;
+
+fn split_keys(contents: &str) -> Vec<String> {
+    contents
+        .split(['\n', ','])
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && !key.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Reads keys from an environment variable. Several keys may be comma separated.
+pub struct EnvVarProvider {
+    var: String,
+}
+
+impl EnvVarProvider {
+    pub fn new(var: &str) -> Self {
+        Self {
+            var: var.to_string(),
+        }
+    }
+}
+
+impl CredentialProvider for EnvVarProvider {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let value = std::env::var(&self.var)
+            .map_err(|_| format!("environment variable {} is not set", self.var))?;
+
+        match split_keys(&value) {
+            keys if keys.is_empty() => {
+                Err(format!("environment variable {} is empty", self.var).into())
+            }
+            keys => Ok(keys),
+        }
+    }
+}
+
+/// Reads keys from a file, one per line or comma separated. Blank lines and `#`
+/// comments are ignored.
+pub struct KeyFileProvider {
+    path: PathBuf,
+}
+
+impl KeyFileProvider {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl CredentialProvider for KeyFileProvider {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|err| format!("unable to read key file {}: {}", self.path.display(), err))?;
+
+        match split_keys(&contents) {
+            keys if keys.is_empty() => {
+                Err(format!("key file {} contains no keys", self.path.display()).into())
+            }
+            keys => Ok(keys),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CredentialConfig {
+    openai_key: Option<String>,
+    #[serde(default)]
+    openai_keys: Vec<String>,
+}
+
+/// Reads keys from the `openai_key` and `openai_keys` fields of a JSON config file.
+pub struct ConfigFileProvider {
+    path: PathBuf,
+}
+
+impl ConfigFileProvider {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl CredentialProvider for ConfigFileProvider {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(&self.path).map_err(|err| {
+            format!(
+                "unable to read config file {}: {}",
+                self.path.display(),
+                err
+            )
+        })?;
+        let config: CredentialConfig = serde_json::from_str(&contents)
+            .map_err(|err| format!("invalid config file {}: {}", self.path.display(), err))?;
+
+        let keys: Vec<String> = config
+            .openai_key
+            .into_iter()
+            .chain(config.openai_keys)
+            .filter(|key| !key.trim().is_empty())
+            .collect();
+
+        match keys.is_empty() {
+            true => Err(format!("config file {} contains no keys", self.path.display()).into()),
+            false => Ok(keys),
+        }
+    }
+}
+
+/// Runs a shell command (e.g. a password manager) and reads keys from its stdout.
+pub struct CommandProvider {
+    command: String,
+}
+
+impl CommandProvider {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl CredentialProvider for CommandProvider {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(|err| format!("unable to run key command `{}`: {}", self.command, err))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "key command `{}` failed with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        match split_keys(&String::from_utf8(output.stdout)?) {
+            keys if keys.is_empty() => {
+                Err(format!("key command `{}` printed no keys", self.command).into())
+            }
+            keys => Ok(keys),
+        }
+    }
+}
+
+/// Tries each provider in order and returns the keys from the first that succeeds.
+#[derive(Default)]
+pub struct ChainProvider {
+    providers: Vec<Box<dyn CredentialProvider>>,
+}
+
+impl ChainProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<P: CredentialProvider + 'static>(mut self, provider: P) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+}
+
+impl CredentialProvider for ChainProvider {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut failures = Vec::new();
+
+        for provider in &self.providers {
+            match provider.api_keys() {
+                Ok(keys) => return Ok(keys),
+                Err(err) => failures.push(err.to_string()),
+            }
+        }
+
+        Err(format!("No API key found. Tried:\n  - {}", failures.join("\n  - ")).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_keys() {
+        let keys = split_keys("# work\nsk-one\n\n sk-two , sk-three\n");
+        assert_eq!(keys, vec!["sk-one", "sk-two", "sk-three"]);
+    }
+
+    #[test]
+    fn test_command_provider() {
+        let provider = CommandProvider::new("echo sk-one; echo sk-two");
+        assert_eq!(provider.api_keys().unwrap(), vec!["sk-one", "sk-two"]);
+
+        let provider = CommandProvider::new("exit 3");
+        assert!(provider.api_keys().is_err());
+    }
+
+    #[test]
+    fn test_chain_reports_every_failure() {
+        let chain = ChainProvider::new()
+            .with(EnvVarProvider::new("ASSISTANT_TEST_UNSET_KEY_VAR"))
+            .with(KeyFileProvider::new("/nonexistent/openai_key"));
+
+        let err = chain.api_keys().unwrap_err().to_string();
+        assert!(err.contains("ASSISTANT_TEST_UNSET_KEY_VAR"));
+        assert!(err.contains("/nonexistent/openai_key"));
+    }
+
+    #[test]
+    fn test_chain_falls_through() {
+        let chain = ChainProvider::new()
+            .with(EnvVarProvider::new("ASSISTANT_TEST_UNSET_KEY_VAR"))
+            .with(CommandProvider::new("echo sk-fallback"));
+
+        assert_eq!(chain.api_key().unwrap(), "sk-fallback");
+    }
+}
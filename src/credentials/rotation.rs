@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::sync::Mutex;
+
+use super::CredentialProvider;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotationStrategy {
+    /// Cycle through the keys in order.
+    RoundRobin,
+    /// Use the key with the most remaining quota, skipping keys that have used
+    /// `quota` tokens or more.
+    QuotaAware { quota: u64 },
+}
+
+struct RotationState {
+    next: usize,
+    usage: Vec<u64>,
+}
+
+/// Rotates requests across several API keys.
+pub struct KeyRotator {
+    keys: Vec<String>,
+    strategy: RotationStrategy,
+    state: Mutex<RotationState>,
+}
+
+impl KeyRotator {
+    pub fn new(keys: Vec<String>, strategy: RotationStrategy) -> Result<Self, Box<dyn Error>> {
+        if keys.is_empty() {
+            return Err("Key rotation requires at least one API key".into());
+        }
+
+        let usage = vec![0; keys.len()];
+        Ok(Self {
+            keys,
+            strategy,
+            state: Mutex::new(RotationState { next: 0, usage }),
+        })
+    }
+
+    /// Tokens recorded against each key, in the order the keys were given.
+    pub fn usage(&self) -> Vec<u64> {
+        self.state.lock().unwrap().usage.clone()
+    }
+}
+
+impl CredentialProvider for KeyRotator {
+    fn api_keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.keys.clone())
+    }
+
+    fn api_key(&self) -> Result<String, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        let index = match self.strategy {
+            RotationStrategy::RoundRobin => {
+                let index = state.next;
+                state.next = (state.next + 1) % self.keys.len();
+                index
+            }
+            RotationStrategy::QuotaAware { quota } => state
+                .usage
+                .iter()
+                .enumerate()
+                .filter(|(_, used)| **used < quota)
+                .min_by_key(|(_, used)| **used)
+                .map(|(index, _)| index)
+                .ok_or("All API keys have exhausted their quota")?,
+        };
+
+        Ok(self.keys[index].clone())
+    }
+
+    fn record_usage(&self, key: &str, tokens: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(index) = self.keys.iter().position(|k| k == key) {
+            state.usage[index] += tokens as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let rotator = KeyRotator::new(keys(), RotationStrategy::RoundRobin).unwrap();
+
+        let picked: Vec<String> = (0..4).map(|_| rotator.api_key().unwrap()).collect();
+        assert_eq!(picked, vec!["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_quota_aware() {
+        let rotator = KeyRotator::new(keys(), RotationStrategy::QuotaAware { quota: 100 }).unwrap();
+
+        rotator.record_usage("a", 50);
+        rotator.record_usage("b", 10);
+        rotator.record_usage("c", 100);
+        assert_eq!(rotator.api_key().unwrap(), "b");
+
+        rotator.record_usage("b", 90);
+        assert_eq!(rotator.api_key().unwrap(), "a");
+
+        rotator.record_usage("a", 50);
+        assert!(rotator.api_key().is_err());
+        assert_eq!(rotator.usage(), vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn test_requires_keys() {
+        assert!(KeyRotator::new(Vec::new(), RotationStrategy::RoundRobin).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::error::Error;
+
+use crate::model_traits::{InsertionModel, Responder};
+use crate::response::Response;
+
+pub const DEFAULT_INSERTION_MARKER: &str = "[insert]";
+
+/// Fills in the middle of a document.
+///
+/// The input is split on the first occurrence of the marker; the model writes the
+/// text between the two halves and the completed document is returned. Input
+/// without a marker is treated as a prefix to be continued.
+pub struct InsertionResponder<T: InsertionModel> {
+    model: T,
+    marker: String,
+}
+
+impl<T: InsertionModel> InsertionResponder<T> {
+    pub fn new(model: T) -> Self {
+        Self {
+            model,
+            marker: DEFAULT_INSERTION_MARKER.to_string(),
+        }
+    }
+
+    pub fn with_marker(mut self, marker: &str) -> Self {
+        self.marker = marker.to_string();
+        self
+    }
+
+    pub fn fill(&self, document: &str) -> Result<String, Box<dyn Error>> {
+        let (prefix, suffix) = match document.split_once(self.marker.as_str()) {
+            Some((prefix, suffix)) => (prefix, suffix),
+            None => (document.trim_end(), ""),
+        };
+
+        let insertion = self.model.insert(prefix, suffix)?;
+
+        Ok(format!("{}{}{}", prefix, insertion, suffix))
+    }
+}
+
+impl<T: InsertionModel> Responder for InsertionResponder<T> {
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>> {
+        Ok(Response::from(self.fill(input)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockInsertionModel;
+
+    impl InsertionModel for MockInsertionModel {
+        fn insert(&self, prefix: &str, suffix: &str) -> Result<String, Box<dyn Error>> {
+            Ok(format!("<{}|{}>", prefix.len(), suffix.len()))
+        }
+    }
+
+    #[test]
+    fn test_fill_with_marker() {
+        let mut responder = InsertionResponder::new(MockInsertionModel);
+
+        let response = responder.respond("Dear Bob,\n[insert]\nRegards").unwrap();
+        assert_eq!(response.text, "Dear Bob,\n<10|8>\nRegards");
+    }
+
+    #[test]
+    fn test_fill_without_marker() {
+        let mut responder = InsertionResponder::new(MockInsertionModel).with_marker("<>");
+
+        let response = responder.respond("fn main() {\n").unwrap();
+        assert_eq!(response.text, "fn main() {<11|0>");
+    }
+}
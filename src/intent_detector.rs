@@ -1,2 +1,7 @@
+pub mod classifier;
+#[allow(clippy::module_inception)]
 pub mod intent_detector;
+pub mod intent_file;
+pub mod llm;
+pub mod saved;
 pub mod zeroshot;
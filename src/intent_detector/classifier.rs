@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::intent_detector::{ConfidencePolicy, IntentDetector, IntentOutcome, IntentResult};
+use super::zeroshot::ZeroShotIntent;
+use crate::model_traits::EmbeddingModel;
+
+/// How `LogisticRegression::fit` trains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainingOptions {
+    pub epochs: usize,
+    pub learning_rate: f32,
+    /// L2 regularization strength.
+    pub l2: f32,
+    /// Every this-many-th example of each intent is held out to calibrate the
+    /// probabilities instead of being trained on. Intents with fewer examples
+    /// aren't held out from; 0 disables calibration.
+    pub calibration_every: usize,
+}
+
+impl Default for TrainingOptions {
+    fn default() -> Self {
+        Self {
+            epochs: 300,
+            learning_rate: 2.0,
+            l2: 1e-4,
+            calibration_every: 5,
+        }
+    }
+}
+
+impl TrainingOptions {
+    fn validate(&self) -> Result<(), String> {
+        if self.epochs == 0 {
+            return Err("epochs must be at least 1".to_string());
+        }
+        if self.learning_rate.is_nan() || self.learning_rate <= 0.0 {
+            return Err("learning rate must be above 0".to_string());
+        }
+        if self.l2.is_nan() || self.l2 < 0.0 {
+            return Err("L2 regularization can't be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A labeled embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    pub embedding: Vec<f32>,
+    pub label: String,
+}
+
+/// Multinomial logistic regression over embeddings.
+///
+/// Probabilities are calibrated with temperature scaling: after training, the
+/// logits are divided by the temperature that best fits held-out examples, so a
+/// probability of 0.8 is right about 80% of the time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogisticRegression {
+    /// The embedding model the classifier was trained on, if it has an id.
+    pub model: Option<String>,
+    pub labels: Vec<String>,
+    /// One row per label.
+    pub weights: Vec<Vec<f32>>,
+    pub biases: Vec<f32>,
+    pub temperature: f32,
+}
+
+impl LogisticRegression {
+    pub fn fit(examples: &[Example], options: &TrainingOptions) -> Result<Self, Box<dyn Error>> {
+        options
+            .validate()
+            .map_err(|err| format!("Invalid training options: {}", err))?;
+        let dimension = match examples.first() {
+            Some(example) => example.embedding.len(),
+            None => return Err("No examples to train on".into()),
+        };
+        if let Some(example) = examples.iter().find(|e| e.embedding.len() != dimension) {
+            return Err(format!(
+                "Example for '{}' has {} dimensions, expected {}",
+                example.label,
+                example.embedding.len(),
+                dimension
+            )
+            .into());
+        }
+
+        let mut labels: Vec<String> = examples.iter().map(|e| e.label.clone()).collect();
+        labels.sort();
+        labels.dedup();
+        if labels.len() < 2 {
+            return Err("Training needs examples of at least two intents".into());
+        }
+
+        let (training, calibration) = split(examples, options.calibration_every);
+        if training.is_empty() {
+            return Err("Calibration holds out every example, leaving none to train on".into());
+        }
+        let mut classifier = Self {
+            model: None,
+            weights: vec![vec![0.0; dimension]; labels.len()],
+            biases: vec![0.0; labels.len()],
+            labels,
+            temperature: 1.0,
+        };
+        classifier.train(&training, options);
+        if !calibration.is_empty() {
+            classifier.calibrate(&calibration);
+        }
+
+        Ok(classifier)
+    }
+
+    /// Full-batch gradient descent on cross-entropy loss.
+    fn train(&mut self, examples: &[&Example], options: &TrainingOptions) {
+        let targets: Vec<usize> = examples.iter().map(|e| self.index(&e.label)).collect();
+        let rate = options.learning_rate / examples.len() as f32;
+
+        for _ in 0..options.epochs {
+            let mut weight_gradients = vec![vec![0.0; self.dimension()]; self.labels.len()];
+            let mut bias_gradients = vec![0.0; self.labels.len()];
+
+            for (example, target) in examples.iter().zip(&targets) {
+                let probabilities = softmax(&self.logits(&example.embedding), 1.0);
+                for (label, probability) in probabilities.iter().enumerate() {
+                    let error = probability - if label == *target { 1.0 } else { 0.0 };
+                    for (gradient, x) in weight_gradients[label].iter_mut().zip(&example.embedding)
+                    {
+                        *gradient += error * x;
+                    }
+                    bias_gradients[label] += error;
+                }
+            }
+
+            for label in 0..self.labels.len() {
+                for (weight, gradient) in
+                    self.weights[label].iter_mut().zip(&weight_gradients[label])
+                {
+                    *weight -= rate * gradient + options.learning_rate * options.l2 * *weight;
+                }
+                self.biases[label] -= rate * bias_gradients[label];
+            }
+        }
+    }
+
+    /// Picks the temperature with the lowest log loss on `examples`, searching
+    /// from 0.05 to 20 on a log scale.
+    fn calibrate(&mut self, examples: &[&Example]) {
+        let logits: Vec<(Vec<f32>, usize)> = examples
+            .iter()
+            .map(|e| (self.logits(&e.embedding), self.index(&e.label)))
+            .collect();
+        let loss = |temperature: f32| -> f32 {
+            logits
+                .iter()
+                .map(|(logits, target)| -softmax(logits, temperature)[*target].max(1e-12).ln())
+                .sum()
+        };
+
+        let (low, high) = (0.05f32.ln(), 20f32.ln());
+        self.temperature = (0..100)
+            .map(|step| (low + (high - low) * step as f32 / 99.0).exp())
+            .min_by(|a, b| loss(*a).total_cmp(&loss(*b)))
+            .unwrap_or(1.0);
+    }
+
+    /// Calibrated probabilities for every label, in the order of `labels`.
+    pub fn predict(&self, embedding: &[f32]) -> Result<Vec<IntentResult>, Box<dyn Error>> {
+        if embedding.len() != self.dimension() {
+            return Err(format!(
+                "Embedding has {} dimensions, but the classifier was trained on {}",
+                embedding.len(),
+                self.dimension()
+            )
+            .into());
+        }
+
+        let probabilities = softmax(&self.logits(embedding), self.temperature);
+        Ok(self
+            .labels
+            .iter()
+            .zip(probabilities)
+            .map(|(label, score)| IntentResult {
+                intent: label.clone(),
+                score,
+            })
+            .collect())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            format!(
+                "unable to read intent classifier {}: {}",
+                path.display(),
+                err
+            )
+        })?;
+        let classifier: Self = serde_json::from_str(&contents)
+            .map_err(|err| format!("invalid intent classifier {}: {}", path.display(), err))?;
+        classifier
+            .validate()
+            .map_err(|err| format!("invalid intent classifier {}: {}", path.display(), err))?;
+        Ok(classifier)
+    }
+
+    /// Checks that a classifier read from disk can be used to predict.
+    fn validate(&self) -> Result<(), String> {
+        if self.labels.is_empty() {
+            return Err("no labels".to_string());
+        }
+        if self.weights.len() != self.labels.len() || self.biases.len() != self.labels.len() {
+            return Err(format!(
+                "{} labels but {} weight rows and {} biases",
+                self.labels.len(),
+                self.weights.len(),
+                self.biases.len()
+            ));
+        }
+        let dimension = self.dimension();
+        if self.weights.iter().any(|row| row.len() != dimension) {
+            return Err("weight rows have different dimensions".to_string());
+        }
+        if !self.labels.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err("labels are not sorted and unique".to_string());
+        }
+        match self.temperature > 0.0 && self.temperature.is_finite() {
+            true => Ok(()),
+            false => Err(format!("temperature {} is not above 0", self.temperature)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so a crash never leaves a half-written file.
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn dimension(&self) -> usize {
+        self.weights.first().map_or(0, Vec::len)
+    }
+
+    fn index(&self, label: &str) -> usize {
+        self.labels
+            .binary_search_by(|l| l.as_str().cmp(label))
+            .unwrap()
+    }
+
+    fn logits(&self, embedding: &[f32]) -> Vec<f32> {
+        self.weights
+            .iter()
+            .zip(&self.biases)
+            .map(|(weights, bias)| {
+                bias + weights
+                    .iter()
+                    .zip(embedding)
+                    .map(|(w, x)| w * x)
+                    .sum::<f32>()
+            })
+            .collect()
+    }
+}
+
+/// Splits off every `every`-th example of each label for calibration.
+fn split(examples: &[Example], every: usize) -> (Vec<&Example>, Vec<&Example>) {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for example in examples {
+        *counts.entry(&example.label).or_default() += 1;
+    }
+
+    examples.iter().partition(|example| {
+        let seen = seen.entry(&example.label).or_default();
+        *seen += 1;
+        every == 0 || counts[example.label.as_str()] < every || !seen.is_multiple_of(every)
+    })
+}
+
+fn softmax(logits: &[f32], temperature: f32) -> Vec<f32> {
+    // Subtracting the largest logit keeps exp() from overflowing.
+    let top = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits
+        .iter()
+        .map(|logit| ((logit - top) / temperature).exp())
+        .collect();
+    let total: f32 = exps.iter().sum();
+    exps.iter().map(|exp| exp / total).collect()
+}
+
+/// Detects intents with a `LogisticRegression` trained on embeddings of labeled
+/// phrases. Scores are calibrated probabilities.
+pub struct ClassifierIntentDetector<E: EmbeddingModel> {
+    pub embedder: E,
+    pub classifier: LogisticRegression,
+    pub confidence: ConfidencePolicy,
+}
+
+impl<E: EmbeddingModel> ClassifierIntentDetector<E> {
+    /// Uses a trained classifier, failing if it was trained on another model's
+    /// embeddings.
+    pub fn new(embedder: E, classifier: LogisticRegression) -> Result<Self, Box<dyn Error>> {
+        if classifier.model.as_deref() != embedder.model_id() {
+            return Err(format!(
+                "The intent classifier was trained on embeddings from model {}, not {}",
+                classifier.model.as_deref().unwrap_or("(unknown)"),
+                embedder.model_id().unwrap_or("(unknown)")
+            )
+            .into());
+        }
+
+        Ok(Self {
+            embedder,
+            classifier,
+            confidence: ConfidencePolicy::default(),
+        })
+    }
+
+    /// Embeds the intents' training phrases and trains a classifier on them.
+    pub fn train(
+        embedder: E,
+        intents: &[ZeroShotIntent],
+        options: &TrainingOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let phrases: Vec<String> = intents
+            .iter()
+            .flat_map(|intent| intent.training_phrases.clone())
+            .collect();
+        let labels = intents.iter().flat_map(|intent| {
+            intent
+                .training_phrases
+                .iter()
+                .map(move |_| intent.intent.clone())
+        });
+
+        let examples: Vec<Example> = embedder
+            .embed_answer(&phrases)?
+            .into_iter()
+            .zip(labels)
+            .map(|(embedding, label)| Example { embedding, label })
+            .collect();
+
+        let mut classifier = LogisticRegression::fit(&examples, options)?;
+        classifier.model = embedder.model_id().map(String::from);
+        Self::new(embedder, classifier)
+    }
+
+    pub fn with_confidence(mut self, confidence: ConfidencePolicy) -> Self {
+        self.confidence = confidence;
+        self
+    }
+}
+
+impl<E: EmbeddingModel> IntentDetector for ClassifierIntentDetector<E> {
+    fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>> {
+        let embedding = self.embedder.embed_question(text.to_string())?;
+        self.classifier.predict(&embedding)
+    }
+
+    fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
+        let scores = self.get_intent_scores(text)?;
+        Ok(self.confidence.classify(scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example(embedding: [f32; 3], label: &str) -> Example {
+        Example {
+            embedding: embedding.to_vec(),
+            label: label.to_string(),
+        }
+    }
+
+    fn examples() -> Vec<Example> {
+        let mut examples = Vec::new();
+        for i in 0..10 {
+            let noise = i as f32 * 0.03;
+            examples.push(example([1.0, noise, 0.1], "greeting"));
+            examples.push(example([noise, 1.0, 0.1], "goodbye"));
+            examples.push(example([0.1, noise, 1.0], "search_web"));
+        }
+        examples
+    }
+
+    #[test]
+    fn test_fit_and_predict() {
+        let classifier = LogisticRegression::fit(&examples(), &TrainingOptions::default()).unwrap();
+
+        assert_eq!(classifier.labels, vec!["goodbye", "greeting", "search_web"]);
+        assert!(classifier.temperature > 0.0);
+
+        let scores = classifier.predict(&[0.9, 0.1, 0.0]).unwrap();
+        let best = scores
+            .iter()
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+            .unwrap();
+        assert_eq!(best.intent, "greeting");
+        assert!(best.score > 0.5);
+        assert!((scores.iter().map(|s| s.score).sum::<f32>() - 1.0).abs() < 1e-5);
+
+        assert!(classifier.predict(&[1.0, 0.0]).is_err());
+        assert!(LogisticRegression::fit(&examples()[..1], &TrainingOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_training_options() {
+        let fit = |options: TrainingOptions| LogisticRegression::fit(&examples(), &options);
+        let defaults = TrainingOptions::default();
+
+        assert!(fit(TrainingOptions {
+            calibration_every: 1,
+            ..defaults
+        })
+        .is_err());
+        assert!(fit(TrainingOptions {
+            epochs: 0,
+            ..defaults
+        })
+        .is_err());
+        assert!(fit(TrainingOptions {
+            learning_rate: 0.0,
+            ..defaults
+        })
+        .is_err());
+        assert!(fit(TrainingOptions {
+            learning_rate: f32::NAN,
+            ..defaults
+        })
+        .is_err());
+        assert!(fit(TrainingOptions {
+            calibration_every: 0,
+            ..defaults
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_calibration_split() {
+        let examples = examples();
+        let (training, calibration) = split(&examples, 5);
+
+        assert_eq!(calibration.len(), 6);
+        assert_eq!(training.len(), 24);
+        assert!(split(&examples[..6], 5).1.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("intent-classifier-{}.json", std::process::id()));
+        let classifier = LogisticRegression::fit(&examples(), &TrainingOptions::default()).unwrap();
+
+        classifier.save(&path).unwrap();
+        assert_eq!(LogisticRegression::load(&path).unwrap(), classifier);
+
+        let invalid = |change: fn(&mut LogisticRegression)| {
+            let mut broken = classifier.clone();
+            change(&mut broken);
+            broken.save(&path).unwrap();
+            LogisticRegression::load(&path).unwrap_err().to_string()
+        };
+        assert!(invalid(|c| {
+            c.biases.pop();
+        })
+        .contains("weight rows"));
+        assert!(invalid(|c| c.weights[0].push(1.0)).contains("different dimensions"));
+        assert!(invalid(|c| c.labels.reverse()).contains("not sorted"));
+        assert!(invalid(|c| c.temperature = 0.0).contains("temperature"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    struct KeywordEmbedder;
+
+    impl EmbeddingModel for KeywordEmbedder {
+        fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+            Ok(documents
+                .iter()
+                .map(|document| {
+                    ["hello", "bye", "search"]
+                        .iter()
+                        .map(|word| document.matches(word).count() as f32 + 0.01)
+                        .collect()
+                })
+                .collect())
+        }
+
+        fn model_id(&self) -> Option<&str> {
+            Some("keywords")
+        }
+    }
+
+    #[test]
+    fn test_classifier_detector() {
+        let intents = vec![
+            ZeroShotIntent::new("greeting", vec!["hello".into(), "hello hello".into()]),
+            ZeroShotIntent::new("goodbye", vec!["bye".into(), "bye now".into()]),
+        ];
+        let detector =
+            ClassifierIntentDetector::train(KeywordEmbedder, &intents, &TrainingOptions::default())
+                .unwrap();
+
+        assert_eq!(detector.classifier.model.as_deref(), Some("keywords"));
+        assert_eq!(
+            detector.detect_intent("well hello").unwrap().intent,
+            "greeting"
+        );
+        assert_eq!(detector.detect_intent("bye bye").unwrap().intent, "goodbye");
+
+        let mut classifier = detector.classifier.clone();
+        classifier.model = Some("other".into());
+        assert!(ClassifierIntentDetector::new(KeywordEmbedder, classifier).is_err());
+    }
+}
@@ -1,6 +1,10 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
+use std::sync::Arc;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct IntentResult {
     pub intent: String,
     pub score: f32,
@@ -12,15 +16,173 @@ impl Display for IntentResult {
     }
 }
 
-pub trait IntentDetector {
+/// What a detector made of some input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntentOutcome {
+    Detected(IntentResult),
+    /// No intent scored high enough. Holds the closest intent, if there were any.
+    Unknown(Option<IntentResult>),
+    /// More than one intent scored within the minimum margin of the best, best first.
+    Ambiguous(Vec<IntentResult>),
+}
+
+impl IntentOutcome {
+    /// The best scoring intent, whether or not it was detected.
+    pub fn best(&self) -> Option<&IntentResult> {
+        match self {
+            IntentOutcome::Detected(intent) => Some(intent),
+            IntentOutcome::Unknown(best) => best.as_ref(),
+            IntentOutcome::Ambiguous(candidates) => candidates.first(),
+        }
+    }
+}
+
+impl Display for IntentOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntentOutcome::Detected(intent) => write!(f, "{}", intent.intent),
+            IntentOutcome::Unknown(_) => write!(f, "unknown"),
+            IntentOutcome::Ambiguous(candidates) => {
+                let names: Vec<&str> = candidates.iter().map(|c| c.intent.as_str()).collect();
+                write!(f, "ambiguous ({})", names.join(", "))
+            }
+        }
+    }
+}
+
+/// How confident a detector has to be to name an intent.
+///
+/// An intent is a candidate if its score is at least its own minimum, or else the
+/// global `min_score`. The best candidate is detected unless another is within
+/// `min_margin` of it. The default policy always detects the best scoring intent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfidencePolicy {
+    pub min_score: Option<f32>,
+    pub min_margin: Option<f32>,
+    pub thresholds: HashMap<String, f32>,
+}
+
+impl ConfidencePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    pub fn with_min_margin(mut self, min_margin: f32) -> Self {
+        self.min_margin = Some(min_margin);
+        self
+    }
+
+    /// The minimum score for one intent, overriding the global minimum.
+    pub fn with_threshold(mut self, intent: &str, min_score: f32) -> Self {
+        self.thresholds.insert(intent.to_string(), min_score);
+        self
+    }
+
+    fn accepts(&self, result: &IntentResult) -> bool {
+        let threshold = self
+            .thresholds
+            .get(&result.intent)
+            .or(self.min_score.as_ref());
+        threshold.is_none_or(|threshold| result.score >= *threshold)
+    }
+
+    pub fn classify(&self, mut scores: Vec<IntentResult>) -> IntentOutcome {
+        scores.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+
+        let best = scores.first().cloned();
+        let mut candidates: Vec<IntentResult> = scores
+            .into_iter()
+            .filter(|result| self.accepts(result))
+            .collect();
+
+        let Some(top) = candidates.first().map(|result| result.score) else {
+            return IntentOutcome::Unknown(best);
+        };
+        match self.min_margin {
+            Some(margin) => candidates.retain(|result| top - result.score < margin),
+            None => candidates.truncate(1),
+        }
+
+        match candidates.len() {
+            1 => IntentOutcome::Detected(candidates.remove(0)),
+            _ => IntentOutcome::Ambiguous(candidates),
+        }
+    }
+}
+
+pub trait IntentDetector: Send + Sync {
     fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>>;
 
-    fn detect_intent(&self, text: &str) -> Result<IntentResult, Box<dyn Error>> {
+    /// Whether `text` has a clear intent. By default the best scoring intent is
+    /// always detected, however low its score.
+    fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
         let scores = self.get_intent_scores(text)?;
+        Ok(ConfidencePolicy::default().classify(scores))
+    }
+
+    fn detect_intent(&self, text: &str) -> Result<IntentResult, Box<dyn Error>> {
+        match self.classify(text)? {
+            IntentOutcome::Detected(intent) => Ok(intent),
+            IntentOutcome::Unknown(_) => Err("No intent detected".into()),
+            outcome => Err(format!("The intent is {}", outcome).into()),
+        }
+    }
+}
+
+impl<D: IntentDetector + ?Sized> IntentDetector for Arc<D> {
+    fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>> {
+        (**self).get_intent_scores(text)
+    }
+
+    fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
+        (**self).classify(text)
+    }
 
+    fn detect_intent(&self, text: &str) -> Result<IntentResult, Box<dyn Error>> {
+        (**self).detect_intent(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(scores: &[(&str, f32)]) -> Vec<IntentResult> {
         scores
-            .into_iter()
-            .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap())
-            .ok_or("No intent detected".into())
+            .iter()
+            .map(|(intent, score)| IntentResult {
+                intent: intent.to_string(),
+                score: *score,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_confidence_policy() {
+        let policy = ConfidencePolicy::new()
+            .with_min_score(0.7)
+            .with_min_margin(0.05)
+            .with_threshold("code_execution", 0.85);
+
+        let outcome = policy.classify(scores(&[("greeting", 0.9), ("goodbye", 0.6)]));
+        assert_eq!(outcome.to_string(), "greeting");
+
+        let outcome = policy.classify(scores(&[("code_execution", 0.8), ("greeting", 0.3)]));
+        assert!(matches!(outcome, IntentOutcome::Unknown(Some(_))));
+        assert_eq!(outcome.best().unwrap().intent, "code_execution");
+
+        let outcome = policy.classify(scores(&[("search_web", 0.81), ("search_files", 0.8)]));
+        assert_eq!(outcome.to_string(), "ambiguous (search_web, search_files)");
+
+        assert_eq!(
+            ConfidencePolicy::default().classify(scores(&[("a", 0.1), ("b", 0.09)])),
+            IntentOutcome::Detected(scores(&[("a", 0.1)]).remove(0))
+        );
+        assert_eq!(policy.classify(Vec::new()), IntentOutcome::Unknown(None));
     }
 }
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::zeroshot::ZeroShotIntent;
+
+const DEFAULT_INTENTS: &str = include_str!("default_intents.yaml");
+
+/// The format of an intents file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntentFileFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl IntentFileFormat {
+    /// The format for a file's extension.
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Ok(IntentFileFormat::Json),
+            Some("yaml" | "yml") => Ok(IntentFileFormat::Yaml),
+            Some("toml") => Ok(IntentFileFormat::Toml),
+            _ => Err(format!(
+                "unknown intents file format for {}: expected .json, .yaml or .toml",
+                path.display()
+            )
+            .into()),
+        }
+    }
+}
+
+/// A set of intent definitions, as written in an intents file.
+///
+/// In YAML:
+///
+/// ```yaml
+/// threshold: 0.75
+/// min_margin: 0.02
+/// intents:
+///   - name: greeting
+///     description: The user says hello.
+///     training_phrases: [hello, hi there]
+///     negative_examples: [hello world program]
+///     threshold: 0.8
+///     metadata:
+///       owner: support
+/// ```
+///
+/// `threshold` at the top is the default for intents that don't set their own.
+/// `min_margin` is how far ahead of the next intent the best one has to be; closer
+/// than that and the input is ambiguous.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IntentFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_margin: Option<f32>,
+    pub intents: Vec<ZeroShotIntent>,
+}
+
+impl IntentFile {
+    /// Reads and validates an intents file, choosing the format by extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let format = IntentFileFormat::from_path(path)?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("unable to read intents file {}: {}", path.display(), err))?;
+
+        Self::parse(&contents, format)
+            .map_err(|err| format!("invalid intents file {}: {}", path.display(), err).into())
+    }
+
+    pub fn parse(contents: &str, format: IntentFileFormat) -> Result<Self, Box<dyn Error>> {
+        let file: IntentFile = match format {
+            IntentFileFormat::Json => serde_json::from_str(contents)?,
+            IntentFileFormat::Yaml => serde_yaml::from_str(contents)?,
+            IntentFileFormat::Toml => toml::from_str(contents)?,
+        };
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// The intents bundled with the assistant.
+    pub fn default_intents() -> Self {
+        Self::parse(DEFAULT_INTENTS, IntentFileFormat::Yaml).expect("invalid default intents")
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.intents.is_empty() {
+            return Err("no intents defined".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for intent in &self.intents {
+            if intent.intent.trim().is_empty() {
+                return Err("an intent has no name".to_string());
+            }
+            if !names.insert(intent.intent.as_str()) {
+                return Err(format!("intent '{}' is defined twice", intent.intent));
+            }
+            if intent.training_phrases.is_empty() {
+                return Err(format!(
+                    "intent '{}' has no training phrases",
+                    intent.intent
+                ));
+            }
+            if !valid_threshold(intent.threshold) {
+                return Err(format!(
+                    "intent '{}' has a threshold outside -1 to 1",
+                    intent.intent
+                ));
+            }
+        }
+
+        match valid_threshold(self.threshold) {
+            true => Ok(()),
+            false => Err("the default threshold is outside -1 to 1".to_string()),
+        }
+    }
+
+    /// The intents, with the file's default threshold applied to those without one.
+    pub fn into_intents(self) -> Vec<ZeroShotIntent> {
+        let threshold = self.threshold;
+        self.intents
+            .into_iter()
+            .map(|intent| ZeroShotIntent {
+                threshold: intent.threshold.or(threshold),
+                ..intent
+            })
+            .collect()
+    }
+}
+
+fn valid_threshold(threshold: Option<f32>) -> bool {
+    threshold.is_none_or(|threshold| (-1.0..=1.0).contains(&threshold))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let json = r#"{
+            "threshold": 0.5,
+            "intents": [
+                {"name": "greeting", "training_phrases": ["hello"], "threshold": 0.8},
+                {"name": "goodbye", "phrases": ["bye"], "metadata": {"owner": "support"}}
+            ]
+        }"#;
+        let yaml = "threshold: 0.5\nintents:\n  - name: greeting\n    training_phrases: [hello]\n    threshold: 0.8\n  - name: goodbye\n    phrases: [bye]\n    metadata:\n      owner: support\n";
+        let toml = "threshold = 0.5\n\n[[intents]]\nname = \"greeting\"\ntraining_phrases = [\"hello\"]\nthreshold = 0.8\n\n[[intents]]\nname = \"goodbye\"\nphrases = [\"bye\"]\nmetadata = { owner = \"support\" }\n";
+
+        let expected = IntentFile::parse(json, IntentFileFormat::Json).unwrap();
+        assert_eq!(
+            IntentFile::parse(yaml, IntentFileFormat::Yaml).unwrap(),
+            expected
+        );
+        assert_eq!(
+            IntentFile::parse(toml, IntentFileFormat::Toml).unwrap(),
+            expected
+        );
+
+        let intents = expected.into_intents();
+        assert_eq!(intents[0].threshold, Some(0.8));
+        assert_eq!(intents[1].threshold, Some(0.5));
+        assert_eq!(intents[1].metadata["owner"], "support");
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let parse = |yaml: &str| {
+            IntentFile::parse(yaml, IntentFileFormat::Yaml)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(parse("intents: []"), "no intents defined");
+        assert_eq!(
+            parse("intents:\n  - name: a\n    training_phrases: []"),
+            "intent 'a' has no training phrases"
+        );
+        assert_eq!(
+            parse("intents:\n  - {name: a, phrases: [x]}\n  - {name: a, phrases: [y]}"),
+            "intent 'a' is defined twice"
+        );
+        assert!(parse("intents:\n  - {name: a, phrases: [x], colour: red}").contains("colour"));
+        assert!(IntentFileFormat::from_path(Path::new("intents.txt")).is_err());
+    }
+
+    #[test]
+    fn test_default_intents() {
+        let intents = IntentFile::default_intents().into_intents();
+
+        assert_eq!(intents.len(), 8);
+        assert!(intents
+            .iter()
+            .any(|intent| intent.intent == "code_execution"));
+    }
+}
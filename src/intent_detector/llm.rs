@@ -0,0 +1,358 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+use super::intent_detector::{ConfidencePolicy, IntentDetector, IntentOutcome, IntentResult};
+use super::intent_file::IntentFile;
+use super::zeroshot::ZeroShotIntent;
+use crate::model_traits::CompletionModel;
+use crate::template::{PromptTemplate, TemplateRegistry};
+
+const CLASSIFICATION_PROMPT: &str = "Classify the user's message as one of the intents below. Answer with the intent's letter only, or 0 if none of them fit.
+
+{intents}
+Message: {input}
+Intent letter:";
+
+/// Intents are labelled with single letters, so each label is a single token and
+/// none is a prefix of another.
+const LABELS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+pub const DEFAULT_EXAMPLES_PER_INTENT: usize = 3;
+/// Completions sampled per input when the model doesn't report logprobs.
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// How `LlmIntentDetector` turns the model's answers into scores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LlmScoring {
+    /// The probability of each intent's letter as the next token. Falls back to
+    /// sampling `DEFAULT_SAMPLES` completions if the model can't report logprobs
+    /// for every label, as with more than four intents on the OpenAI API.
+    #[default]
+    Logprobs,
+    /// The share of this many completions that chose each intent. Only useful with
+    /// a temperature above zero.
+    Sampling(usize),
+}
+
+/// Detects intents by asking a completion model to pick one.
+///
+/// The prompt labels the intents with letters, with their descriptions and a few
+/// training phrases as examples, and asks for a letter, so answers can only be one
+/// of the intents or 0 for none. Scores are probabilities from 0 to 1, so this
+/// detector has its own confidence policy: intent thresholds, which are
+/// similarities, don't apply. At most 26 intents are supported.
+pub struct LlmIntentDetector<M: CompletionModel> {
+    model: M,
+    intents: Vec<ZeroShotIntent>,
+    prompt: PromptTemplate,
+    examples_per_intent: usize,
+    scoring: LlmScoring,
+    confidence: ConfidencePolicy,
+}
+
+impl<M: CompletionModel> LlmIntentDetector<M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            intents: Vec::new(),
+            prompt: PromptTemplate::parse("intent_classification", CLASSIFICATION_PROMPT)
+                .expect("The default classification prompt is a valid template"),
+            examples_per_intent: DEFAULT_EXAMPLES_PER_INTENT,
+            scoring: LlmScoring::default(),
+            confidence: ConfidencePolicy::default(),
+        }
+    }
+
+    pub fn add_intent(mut self, intent: &str, description: &str, examples: Vec<String>) -> Self {
+        let mut intent = ZeroShotIntent::new(intent, examples);
+        intent.description = Some(description.to_string());
+        self.intents.push(intent);
+        self
+    }
+
+    pub fn add_intents(mut self, intents: Vec<ZeroShotIntent>) -> Self {
+        self.intents.extend(intents);
+        self
+    }
+
+    pub fn with_default_intents(self) -> Self {
+        self.add_intents(IntentFile::default_intents().into_intents())
+    }
+
+    /// Adds the intents from a JSON, YAML or TOML intents file. See `IntentFile`.
+    /// The file's thresholds and margin are for similarities and are ignored.
+    pub fn with_intents_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn Error>> {
+        let file = IntentFile::load(path)?;
+        Ok(self.add_intents(file.into_intents()))
+    }
+
+    /// Replaces the prompt, failing if it uses variables other than `{intents}` and
+    /// `{input}`.
+    pub fn with_prompt(mut self, template: PromptTemplate) -> Result<Self, Box<dyn Error>> {
+        let variables = HashSet::from(["intents".to_string(), "input".to_string()]);
+        template.validate(&TemplateRegistry::new(), &variables)?;
+        self.prompt = template;
+        Ok(self)
+    }
+
+    /// How many training phrases to show per intent.
+    pub fn with_examples_per_intent(mut self, examples: usize) -> Self {
+        self.examples_per_intent = examples;
+        self
+    }
+
+    pub fn with_scoring(mut self, scoring: LlmScoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    /// How confident the detector must be to name an intent, as probabilities.
+    pub fn with_confidence(mut self, confidence: ConfidencePolicy) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    pub fn prompt(&self, input: &str) -> Result<String, Box<dyn Error>> {
+        let intents: Vec<String> = self
+            .intents
+            .iter()
+            .zip(LABELS.chars())
+            .map(|(intent, label)| {
+                let mut entry = format!("{}. {}", label, intent.intent);
+                if let Some(description) = &intent.description {
+                    entry += &format!(": {}", description);
+                }
+                let examples: Vec<String> = intent
+                    .training_phrases
+                    .iter()
+                    .take(self.examples_per_intent)
+                    .map(|phrase| format!("\"{}\"", phrase))
+                    .collect();
+                if !examples.is_empty() {
+                    entry += &format!("\n   Examples: {}", examples.join(", "));
+                }
+                entry + "\n"
+            })
+            .collect();
+
+        let variables = HashMap::from([
+            ("intents".to_string(), intents.concat()),
+            ("input".to_string(), input.trim().to_string()),
+        ]);
+        self.prompt.render(&variables, &TemplateRegistry::new())
+    }
+
+    /// The intent an answer names, by letter or else by name: 0 for none, otherwise
+    /// the intent's position counting from 1. `None` if the answer isn't a label.
+    fn label(&self, answer: &str) -> Option<usize> {
+        let answer = answer.trim();
+        let mut chars = answer.chars();
+        let first = chars.next()?;
+        let standalone = chars.next().is_none_or(|next| !next.is_alphanumeric());
+
+        if standalone && first == '0' {
+            return Some(0);
+        }
+        if standalone {
+            if let Some(index) = LABELS.find(first) {
+                return (index < self.intents.len()).then_some(index + 1);
+            }
+        }
+
+        let answer = answer.to_lowercase();
+        self.intents
+            .iter()
+            .position(|intent| answer.starts_with(&intent.intent.to_lowercase()))
+            .map(|index| index + 1)
+    }
+
+    fn sample(&self, prompt: &str, samples: usize) -> Result<Vec<f32>, Box<dyn Error>> {
+        let mut votes = vec![0.0; self.intents.len()];
+        let samples = samples.max(1);
+
+        for _ in 0..samples {
+            let answer = self.model.complete_with_stop(prompt, &["\n".to_string()])?;
+            if let Some(label) = self.label(&answer).filter(|label| *label > 0) {
+                votes[label - 1] += 1.0 / samples as f32;
+            }
+        }
+
+        Ok(votes)
+    }
+}
+
+impl<M: CompletionModel> IntentDetector for LlmIntentDetector<M> {
+    fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>> {
+        if self.intents.is_empty() {
+            return Err("No intents to detect".into());
+        }
+        if self.intents.len() > LABELS.len() {
+            return Err(format!("At most {} intents can be labelled", LABELS.len()).into());
+        }
+        let prompt = self.prompt(text)?;
+
+        let scores = match self.scoring {
+            LlmScoring::Sampling(samples) => self.sample(&prompt, samples)?,
+            LlmScoring::Logprobs => {
+                match self
+                    .model
+                    .next_token_logprobs(&prompt, self.intents.len() + 1)?
+                {
+                    Some(tokens) => {
+                        let mut scores = vec![0.0; self.intents.len()];
+                        for (token, logprob) in tokens {
+                            if let Some(label) = self.label(&token).filter(|label| *label > 0) {
+                                scores[label - 1] += logprob.exp();
+                            }
+                        }
+                        scores
+                    }
+                    None => self.sample(&prompt, DEFAULT_SAMPLES)?,
+                }
+            }
+        };
+
+        Ok(self
+            .intents
+            .iter()
+            .zip(scores)
+            .map(|(intent, score)| IntentResult {
+                intent: intent.intent.clone(),
+                score,
+            })
+            .collect())
+    }
+
+    fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
+        let scores = self.get_intent_scores(text)?;
+        Ok(self.confidence.classify(scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_traits::TokenLogprobs;
+    use std::sync::Mutex;
+
+    fn detector<M: CompletionModel>(model: M) -> LlmIntentDetector<M> {
+        LlmIntentDetector::new(model)
+            .add_intent(
+                "greeting",
+                "The user says hello.",
+                vec!["hi".into(), "hello".into(), "hey".into(), "howdy".into()],
+            )
+            .add_intent("goodbye", "The user leaves.", vec!["bye".into()])
+            .with_confidence(ConfidencePolicy::new().with_min_score(0.5))
+    }
+
+    struct LogprobModel;
+
+    impl CompletionModel for LogprobModel {
+        fn complete(&self, _prompt: &str) -> Result<String, Box<dyn Error>> {
+            unreachable!("scores come from logprobs")
+        }
+
+        fn next_token_logprobs(
+            &self,
+            prompt: &str,
+            _top: usize,
+        ) -> Result<Option<TokenLogprobs>, Box<dyn Error>> {
+            let tokens = match prompt.contains("Message: see ya") {
+                true => vec![
+                    (" B", 0.8f32.ln()),
+                    (" A", 0.1f32.ln()),
+                    ("B", 0.05f32.ln()),
+                ],
+                false => vec![
+                    (" 0", 0.7f32.ln()),
+                    (" A", 0.2f32.ln()),
+                    (" yes", 0.1f32.ln()),
+                ],
+            };
+            Ok(Some(
+                tokens
+                    .into_iter()
+                    .map(|(token, logprob)| (token.to_string(), logprob))
+                    .collect(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_logprob_scores() {
+        let detector = detector(LogprobModel);
+
+        let scores = detector.get_intent_scores("see ya").unwrap();
+        assert_eq!(scores[1].intent, "goodbye");
+        assert!((scores[1].score - 0.85).abs() < 1e-5);
+        assert!((scores[0].score - 0.1).abs() < 1e-5);
+        assert_eq!(detector.detect_intent("see ya").unwrap().intent, "goodbye");
+
+        assert!(matches!(
+            detector.classify("qwerty").unwrap(),
+            IntentOutcome::Unknown(_)
+        ));
+    }
+
+    /// Has no logprobs, and answers from a script in turn.
+    struct SamplingModel {
+        answers: Mutex<Vec<&'static str>>,
+    }
+
+    impl CompletionModel for SamplingModel {
+        fn complete(&self, _prompt: &str) -> Result<String, Box<dyn Error>> {
+            Ok(self.answers.lock().unwrap().remove(0).to_string())
+        }
+    }
+
+    #[test]
+    fn test_sampled_scores() {
+        let detector = detector(SamplingModel {
+            answers: Mutex::new(vec![" A", "greeting", " B", " banana", " A. greeting"]),
+        });
+
+        let scores = detector.get_intent_scores("hello there").unwrap();
+        assert!((scores[0].score - 0.6).abs() < 1e-5);
+        assert!((scores[1].score - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_prompt() {
+        let prompt = detector(LogprobModel)
+            .with_examples_per_intent(2)
+            .prompt(" how's it going\n")
+            .unwrap();
+
+        assert!(prompt.contains(
+            "A. greeting: The user says hello.\n   Examples: \"hi\", \"hello\"\nB. goodbye"
+        ));
+        assert!(prompt.ends_with("Message: how's it going\nIntent letter:"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let detector = detector(LogprobModel);
+
+        assert_eq!(detector.label(" B"), Some(2));
+        assert_eq!(detector.label("A) greeting"), Some(1));
+        assert_eq!(detector.label("0"), Some(0));
+        assert_eq!(detector.label("C"), None);
+        assert_eq!(detector.label("Goodbye"), Some(2));
+        assert_eq!(detector.label("12"), None);
+    }
+
+    #[test]
+    fn test_intent_thresholds_ignored() {
+        let mut goodbye = ZeroShotIntent::new("goodbye", vec!["bye".into()]);
+        goodbye.threshold = Some(0.95);
+        let detector = LlmIntentDetector::new(LogprobModel)
+            .add_intent("greeting", "The user says hello.", vec!["hi".into()])
+            .add_intents(vec![goodbye])
+            .with_confidence(ConfidencePolicy::new().with_min_score(0.5));
+
+        // 0.85 is below the similarity threshold, but it's a probability.
+        assert_eq!(detector.detect_intent("see ya").unwrap().intent, "goodbye");
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::zeroshot::ZeroShotIntent;
+
+/// A stable hash of a phrase, used to tell which saved embeddings are still current.
+/// FNV-1a, so it doesn't change between builds or platforms.
+pub fn phrase_hash(phrase: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in phrase.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhraseEmbedding {
+    pub hash: String,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedIntent {
+    pub definition: ZeroShotIntent,
+    pub embeddings: Vec<PhraseEmbedding>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub negative_embeddings: Vec<PhraseEmbedding>,
+}
+
+/// A built zero-shot intent detector as saved to disk: the intents, the embedding
+/// of every phrase keyed by the phrase's hash, and the model that made them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedIntentEmbeddings {
+    /// The embedding model's id, if it has one.
+    pub model: Option<String>,
+    pub dimension: usize,
+    pub intents: Vec<SavedIntent>,
+}
+
+impl SavedIntentEmbeddings {
+    /// Reads saved embeddings, or `None` if `path` doesn't exist.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = path.as_ref();
+        let saved: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|err| {
+                format!("invalid intent embeddings file {}: {}", path.display(), err)
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let wrong_size = saved
+            .embeddings()
+            .find(|(_, embedding)| embedding.len() != saved.dimension);
+        if let Some((hash, embedding)) = wrong_size {
+            return Err(format!(
+                "invalid intent embeddings file {}: phrase {} has {} dimensions, expected {}",
+                path.display(),
+                hash,
+                embedding.len(),
+                saved.dimension
+            )
+            .into());
+        }
+
+        Ok(Some(saved))
+    }
+
+    /// Writes to a temporary file first, so an interrupted write leaves the old file.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Fails unless the embeddings were made by `model`.
+    pub fn check_model(&self, model: Option<&str>) -> Result<(), Box<dyn Error>> {
+        match self.model.as_deref() == model {
+            true => Ok(()),
+            false => Err(format!(
+                "intent embeddings were made with model {}, not {}",
+                self.model.as_deref().unwrap_or("(unknown)"),
+                model.unwrap_or("(unknown)")
+            )
+            .into()),
+        }
+    }
+
+    /// Every saved embedding by phrase hash.
+    pub fn by_hash(&self) -> HashMap<String, Vec<f32>> {
+        self.embeddings()
+            .map(|(hash, embedding)| (hash.to_string(), embedding.to_vec()))
+            .collect()
+    }
+
+    fn embeddings(&self) -> impl Iterator<Item = (&str, &[f32])> {
+        self.intents
+            .iter()
+            .flat_map(|intent| intent.embeddings.iter().chain(&intent.negative_embeddings))
+            .map(|phrase| (phrase.hash.as_str(), phrase.embedding.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phrase_hash() {
+        assert_eq!(phrase_hash(""), "cbf29ce484222325");
+        assert_eq!(phrase_hash("a"), "af63dc4c8601ec8c");
+        assert_ne!(phrase_hash("hello"), phrase_hash("hello "));
+    }
+}
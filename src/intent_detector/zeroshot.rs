@@ -1,33 +1,168 @@
-use super::intent_detector::{IntentDetector, IntentResult};
+use super::intent_detector::{ConfidencePolicy, IntentDetector, IntentOutcome, IntentResult};
+use super::intent_file::IntentFile;
+use super::saved::{phrase_hash, PhraseEmbedding, SavedIntent, SavedIntentEmbeddings};
 use crate::model_traits::EmbeddingModel;
 use crate::similarity::cosine_similarity;
-use derive_builder::Builder;
-use reqwest::get;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
 
-#[derive(Clone, PartialEq, Eq)]
+/// An intent and the phrases that define it. Also the schema of an intent in an
+/// intents file, where `intent` is written `name`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ZeroShotIntent {
+    #[serde(rename = "name", alias = "intent")]
     pub intent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(alias = "phrases")]
     pub training_phrases: Vec<String>,
+    /// Phrases that look like this intent but aren't. Input closer to one of these
+    /// than to any training phrase is penalized.
+    #[serde(default, alias = "negatives", skip_serializing_if = "Vec::is_empty")]
+    pub negative_examples: Vec<String>,
+    /// The minimum score for this intent to be detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
 }
 
 impl ZeroShotIntent {
-    fn new(intent: &str, training_phrases: Vec<String>) -> Self {
+    pub fn new(intent: &str, training_phrases: Vec<String>) -> Self {
         Self {
             intent: intent.to_string(),
+            description: None,
             training_phrases,
+            negative_examples: Vec::new(),
+            threshold: None,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+/// How a `ZeroShotIntentDetector` turns similarities to training phrases into a
+/// score per intent.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ScoringStrategy {
+    /// The similarity to the closest training phrase.
+    #[default]
+    Max,
+    /// The mean similarity to all training phrases.
+    Mean,
+    /// The mean similarity to the `k` closest training phrases.
+    TopKMean(usize),
+    /// The similarity to the mean of the training phrase embeddings.
+    Centroid,
+    /// The `k` closest training phrases of any intent vote for their intent,
+    /// weighted by the softmax of their similarity over `temperature`. Scores are
+    /// the share of the vote, from 0 to 1, rather than similarities, so negative
+    /// examples don't lower them.
+    SoftmaxKnn { k: usize, temperature: f32 },
+}
+
+impl ScoringStrategy {
+    /// Whether scores are similarities, which negative examples can be weighed against.
+    fn scores_similarity(&self) -> bool {
+        !matches!(self, ScoringStrategy::SoftmaxKnn { .. })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            ScoringStrategy::TopKMean(0) | ScoringStrategy::SoftmaxKnn { k: 0, .. } => {
+                Err("k must be at least 1".to_string())
+            }
+            ScoringStrategy::SoftmaxKnn { temperature, .. }
+                if temperature.is_nan() || temperature <= 0.0 =>
+            {
+                Err("temperature must be above 0".to_string())
+            }
+            _ => Ok(()),
         }
     }
 }
 
 pub struct ZeroShotEmbeddedIntent {
-    pub intent: String,
+    pub definition: ZeroShotIntent,
+    /// One per training phrase, in order.
     pub embeddings: Vec<Vec<f32>>,
+    /// One per negative example, in order.
+    pub negative_embeddings: Vec<Vec<f32>>,
+    /// The mean of `embeddings`.
+    pub centroid: Vec<f32>,
+}
+
+impl ZeroShotEmbeddedIntent {
+    fn new(
+        definition: ZeroShotIntent,
+        embeddings: Vec<Vec<f32>>,
+        negative_embeddings: Vec<Vec<f32>>,
+    ) -> Self {
+        let mut centroid = vec![0.0; embeddings.first().map_or(0, Vec::len)];
+        for embedding in &embeddings {
+            for (total, value) in centroid.iter_mut().zip(embedding) {
+                *total += value / embeddings.len() as f32;
+            }
+        }
+
+        Self {
+            definition,
+            embeddings,
+            negative_embeddings,
+            centroid,
+        }
+    }
+
+    fn similarities(&self, embedding: &[f32]) -> Vec<f32> {
+        self.embeddings
+            .iter()
+            .map(|other| cosine_similarity(embedding, other))
+            .collect()
+    }
+
+    /// How much closer the embedding is to a negative example than to any training
+    /// phrase, if at all.
+    fn penalty(&self, embedding: &[f32], similarities: &[f32]) -> f32 {
+        let negative = self
+            .negative_embeddings
+            .iter()
+            .map(|other| cosine_similarity(embedding, other))
+            .fold(f32::NEG_INFINITY, f32::max);
+        (negative - max(similarities)).max(0.0)
+    }
+
+    fn saved(&self) -> SavedIntent {
+        let saved = |phrases: &[String], embeddings: &[Vec<f32>]| {
+            phrases
+                .iter()
+                .zip(embeddings)
+                .map(|(phrase, embedding)| PhraseEmbedding {
+                    hash: phrase_hash(phrase),
+                    embedding: embedding.clone(),
+                })
+                .collect()
+        };
+
+        SavedIntent {
+            definition: self.definition.clone(),
+            embeddings: saved(&self.definition.training_phrases, &self.embeddings),
+            negative_embeddings: saved(
+                &self.definition.negative_examples,
+                &self.negative_embeddings,
+            ),
+        }
+    }
 }
 
 pub struct ZeroShotIntentDetectorBuilder<T: EmbeddingModel> {
     embedder: T,
     intents: Vec<ZeroShotIntent>,
+    saved: HashMap<String, Vec<f32>>,
+    dimension: Option<usize>,
+    confidence: ConfidencePolicy,
+    scoring: ScoringStrategy,
 }
 
 impl<T: EmbeddingModel> ZeroShotIntentDetectorBuilder<T> {
@@ -35,6 +170,10 @@ impl<T: EmbeddingModel> ZeroShotIntentDetectorBuilder<T> {
         Self {
             embedder,
             intents: Vec::new(),
+            saved: HashMap::new(),
+            dimension: None,
+            confidence: ConfidencePolicy::default(),
+            scoring: ScoringStrategy::default(),
         }
     }
 
@@ -50,177 +189,475 @@ impl<T: EmbeddingModel> ZeroShotIntentDetectorBuilder<T> {
     }
 
     pub fn with_default_intents(mut self) -> Result<Self, Box<dyn Error>> {
-        let intents = get_default_intents();
-        self.intents.extend(intents);
+        self.intents
+            .extend(IntentFile::default_intents().into_intents());
         Ok(self)
     }
 
-    pub fn build(self) -> ZeroShotIntentDetector<T> {
-        let mut intent_embeddings = Vec::new();
+    /// Adds the intents from a JSON, YAML or TOML intents file. See `IntentFile`.
+    pub fn with_intents_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Box<dyn Error>> {
+        let file = IntentFile::load(path)?;
+        if let Some(margin) = file.min_margin {
+            self.confidence.min_margin = Some(margin);
+        }
+        self.intents.extend(file.into_intents());
+        Ok(self)
+    }
 
+    /// Fails if `k` is 0 or the temperature isn't above 0.
+    pub fn with_scoring(mut self, scoring: ScoringStrategy) -> Result<Self, Box<dyn Error>> {
+        scoring.validate()?;
+        self.scoring = scoring;
+        Ok(self)
+    }
+
+    /// How confident the detector must be to name an intent. Thresholds set on
+    /// intents apply unless the policy has its own for that intent.
+    pub fn with_confidence(mut self, confidence: ConfidencePolicy) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// Reuses embeddings saved by `ZeroShotIntentDetector::save` for phrases that
+    /// haven't changed, so only new phrases are embedded. Does nothing if `path`
+    /// doesn't exist, and fails if the embeddings were made by another model.
+    pub fn with_saved_embeddings<P: AsRef<Path>>(self, path: P) -> Result<Self, Box<dyn Error>> {
+        match SavedIntentEmbeddings::read(path)? {
+            Some(saved) => self.with_saved(&saved),
+            None => Ok(self),
+        }
+    }
+
+    /// Like `with_saved_embeddings`, for a cache that can be rebuilt: if `path` can't
+    /// be read or its embeddings don't fit this embedder, it's ignored and every
+    /// phrase is embedded again. The reason it was ignored is returned alongside.
+    pub fn with_cached_embeddings<P: AsRef<Path>>(self, path: P) -> (Self, Option<Box<dyn Error>>) {
+        let saved = SavedIntentEmbeddings::read(path).and_then(|saved| match saved {
+            Some(saved) => self.check_saved(&saved).map(|_| Some(saved)),
+            None => Ok(None),
+        });
+
+        match saved {
+            Ok(Some(saved)) => (self.use_saved(&saved), None),
+            Ok(None) => (self, None),
+            Err(err) => (self, Some(err)),
+        }
+    }
+
+    fn with_saved(self, saved: &SavedIntentEmbeddings) -> Result<Self, Box<dyn Error>> {
+        self.check_saved(saved)?;
+        Ok(self.use_saved(saved))
+    }
+
+    fn check_saved(&self, saved: &SavedIntentEmbeddings) -> Result<(), Box<dyn Error>> {
+        saved.check_model(self.embedder.model_id())?;
+        match self.dimension {
+            Some(dimension) if dimension != saved.dimension => Err(format!(
+                "saved intent embeddings have {} dimensions, expected {}",
+                saved.dimension, dimension
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn use_saved(mut self, saved: &SavedIntentEmbeddings) -> Self {
+        self.dimension = Some(saved.dimension);
+        self.saved.extend(saved.by_hash());
+        self
+    }
+
+    pub fn build(self) -> Result<ZeroShotIntentDetector<T>, Box<dyn Error>> {
         if self.intents.is_empty() {
-            todo!("Either error or add default intents. Not sure which yet.")
+            return Err("No intents to detect".into());
+        }
+
+        let mut embeddings = self.saved;
+        let mut missing: Vec<String> = Vec::new();
+        for phrase in self.intents.iter().flat_map(|intent| {
+            intent
+                .training_phrases
+                .iter()
+                .chain(&intent.negative_examples)
+        }) {
+            if !embeddings.contains_key(&phrase_hash(phrase)) && !missing.contains(phrase) {
+                missing.push(phrase.clone());
+            }
         }
 
-        for intent in self.intents {
-            let embedding = self
-                .embedder
-                .embed_answer(&intent.training_phrases)
-                .unwrap();
+        if !missing.is_empty() {
+            let new = self.embedder.embed_answer(&missing)?;
+            for (phrase, embedding) in missing.iter().zip(new) {
+                if let Some(dimension) = self.dimension.filter(|d| *d != embedding.len()) {
+                    return Err(format!(
+                        "the embedder returned {} dimensions, but saved intent embeddings have {}",
+                        embedding.len(),
+                        dimension
+                    )
+                    .into());
+                }
+                embeddings.insert(phrase_hash(phrase), embedding);
+            }
+        }
 
-            intent_embeddings.push(ZeroShotEmbeddedIntent {
-                intent: intent.intent,
-                embeddings: embedding,
-            });
+        let lookup = |phrases: &[String]| -> Vec<Vec<f32>> {
+            phrases
+                .iter()
+                .map(|phrase| embeddings[&phrase_hash(phrase)].clone())
+                .collect()
+        };
+        let mut confidence = self.confidence;
+        for intent in &self.intents {
+            if let Some(threshold) = intent.threshold {
+                confidence
+                    .thresholds
+                    .entry(intent.intent.clone())
+                    .or_insert(threshold);
+            }
         }
 
-        ZeroShotIntentDetector {
+        let intents = self
+            .intents
+            .into_iter()
+            .map(|definition| {
+                let embeddings = lookup(&definition.training_phrases);
+                let negative_embeddings = lookup(&definition.negative_examples);
+                ZeroShotEmbeddedIntent::new(definition, embeddings, negative_embeddings)
+            })
+            .collect();
+
+        Ok(ZeroShotIntentDetector {
             embedder: self.embedder,
-            intents: intent_embeddings,
-        }
+            intents,
+            confidence,
+            scoring: self.scoring,
+        })
     }
 }
 
 pub struct ZeroShotIntentDetector<T: EmbeddingModel> {
     pub embedder: T,
     pub intents: Vec<ZeroShotEmbeddedIntent>,
+    pub confidence: ConfidencePolicy,
+    /// Can be changed after building, e.g. to compare strategies on the same
+    /// embeddings.
+    pub scoring: ScoringStrategy,
 }
 
 impl<T: EmbeddingModel> ZeroShotIntentDetector<T> {
     pub fn builder(embedder: T) -> ZeroShotIntentDetectorBuilder<T> {
         ZeroShotIntentDetectorBuilder::new(embedder)
     }
+
+    /// Loads a detector saved with `save`, without embedding anything.
+    pub fn load<P: AsRef<Path>>(embedder: T, path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let saved = SavedIntentEmbeddings::read(path)?
+            .ok_or_else(|| format!("no intent embeddings at {}", path.display()))?;
+        let intents = saved
+            .intents
+            .iter()
+            .map(|intent| intent.definition.clone())
+            .collect();
+
+        Self::builder(embedder)
+            .with_saved(&saved)?
+            .add_intents(intents)
+            .build()
+    }
+
+    /// Saves the intents and their embeddings, along with the embedding model's id
+    /// and the embedding size.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let dimension = self
+            .intents
+            .iter()
+            .flat_map(|intent| &intent.embeddings)
+            .map(|embedding| embedding.len())
+            .next()
+            .unwrap_or_default();
+
+        SavedIntentEmbeddings {
+            model: self.embedder.model_id().map(String::from),
+            dimension,
+            intents: self.intents.iter().map(|intent| intent.saved()).collect(),
+        }
+        .write(path)
+    }
 }
 
 impl<T: EmbeddingModel> IntentDetector for ZeroShotIntentDetector<T> {
     fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>> {
         let embedding = self.embedder.embed_question(text.to_string())?;
+        let similarities: Vec<Vec<f32>> = self
+            .intents
+            .iter()
+            .map(|intent| intent.similarities(&embedding))
+            .collect();
 
-        let mut scores = Vec::new();
-        for intent in &self.intents {
-            // get similarity score between the embedding and the intent
+        let scores: Vec<f32> = match self.scoring {
+            ScoringStrategy::Max => similarities.iter().map(|s| max(s)).collect(),
+            ScoringStrategy::Mean => similarities.iter().map(|s| mean(s)).collect(),
+            ScoringStrategy::TopKMean(k) => similarities
+                .iter()
+                .map(|s| {
+                    let mut s = s.clone();
+                    s.sort_by(|a, b| b.total_cmp(a));
+                    s.truncate(k.max(1));
+                    mean(&s)
+                })
+                .collect(),
+            ScoringStrategy::Centroid => self
+                .intents
+                .iter()
+                .map(|intent| cosine_similarity(&embedding, &intent.centroid))
+                .collect(),
+            ScoringStrategy::SoftmaxKnn { k, temperature } => {
+                softmax_knn(&similarities, k, temperature)
+            }
+        };
+
+        Ok(self
+            .intents
+            .iter()
+            .zip(scores)
+            .zip(&similarities)
+            .map(|((intent, score), similarities)| IntentResult {
+                intent: intent.definition.intent.clone(),
+                score: match self.scoring.scores_similarity() {
+                    true => score - intent.penalty(&embedding, similarities),
+                    false => score,
+                },
+            })
+            .collect())
+    }
+
+    fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
+        let scores = self.get_intent_scores(text)?;
+        Ok(self.confidence.classify(scores))
+    }
+}
 
-            let score = intent
-                .embeddings
+fn max(values: &[f32]) -> f32 {
+    values.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+/// Each intent's share of the softmax-weighted votes of the `k` most similar
+/// phrases overall.
+fn softmax_knn(similarities: &[Vec<f32>], k: usize, temperature: f32) -> Vec<f32> {
+    let mut neighbours: Vec<(usize, f32)> = similarities
+        .iter()
+        .enumerate()
+        .flat_map(|(intent, similarities)| similarities.iter().map(move |s| (intent, *s)))
+        .collect();
+    neighbours.sort_by(|a, b| b.1.total_cmp(&a.1));
+    neighbours.truncate(k.max(1));
+
+    // Subtracting the largest similarity keeps exp() from overflowing.
+    let top = neighbours.first().map_or(0.0, |(_, s)| *s);
+    let mut votes = vec![0.0; similarities.len()];
+    for (intent, similarity) in &neighbours {
+        votes[*intent] += ((similarity - top) / temperature).exp();
+    }
+
+    let total: f32 = votes.iter().sum();
+    votes.iter().map(|vote| vote / total).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct KeywordEmbedder;
+
+    impl EmbeddingModel for KeywordEmbedder {
+        fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+            Ok(documents
                 .iter()
-                .map(|intent_embedding| cosine_similarity(&embedding, intent_embedding))
-                .max_by(|a, b| a.partial_cmp(b).unwrap())
-                .unwrap();
+                .map(|document| {
+                    ["hello", "bye", "world"]
+                        .iter()
+                        .map(|word| document.matches(word).count() as f32 + 0.01)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_negative_examples_and_thresholds() {
+        let mut greeting = ZeroShotIntent::new("greeting", vec!["hello".into()]);
+        greeting.negative_examples = vec!["hello world".into()];
+        greeting.threshold = Some(0.5);
+        let mut goodbye = ZeroShotIntent::new("goodbye", vec!["bye".into()]);
+        goodbye.threshold = Some(0.9);
 
-            scores.push(IntentResult {
-                intent: intent.intent.clone(),
-                score,
-            });
+        let detector = ZeroShotIntentDetector::builder(KeywordEmbedder)
+            .add_intents(vec![greeting, goodbye])
+            .build()
+            .unwrap();
+
+        assert_eq!(detector.detect_intent("hello").unwrap().intent, "greeting");
+
+        let scores = detector.get_intent_scores("hello world").unwrap();
+        assert!(scores[0].score < 0.5, "{}", scores[0]);
+
+        assert!(detector.detect_intent("world").is_err());
+    }
+
+    #[test]
+    fn test_scoring_strategies() {
+        let mut detector = ZeroShotIntentDetector::builder(KeywordEmbedder)
+            .add_intent("noisy", vec!["hello".into(), "world world world".into()])
+            .add_intent("steady", vec!["hello world".into(), "hello world".into()])
+            .build()
+            .unwrap();
+        let mut best = |scoring| {
+            detector.scoring = scoring;
+            detector.detect_intent("hello").unwrap().intent
+        };
+
+        assert_eq!(best(ScoringStrategy::Max), "noisy");
+        assert_eq!(best(ScoringStrategy::TopKMean(1)), "noisy");
+        assert_eq!(best(ScoringStrategy::Mean), "steady");
+        assert_eq!(best(ScoringStrategy::Centroid), "steady");
+        assert_eq!(
+            best(ScoringStrategy::SoftmaxKnn {
+                k: 3,
+                temperature: 0.1
+            }),
+            "noisy"
+        );
+
+        let votes = softmax_knn(&[vec![0.9, 0.1], vec![0.8]], 2, 1.0);
+        assert!((votes.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!(votes[0] > votes[1]);
+
+        let builder = || ZeroShotIntentDetector::builder(KeywordEmbedder);
+        assert!(builder()
+            .with_scoring(ScoringStrategy::TopKMean(0))
+            .is_err());
+        assert!(builder()
+            .with_scoring(ScoringStrategy::SoftmaxKnn {
+                k: 3,
+                temperature: 0.0
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_softmax_knn_ignores_negatives() {
+        let mut greeting = ZeroShotIntent::new("greeting", vec!["hello".into()]);
+        greeting.negative_examples = vec!["hello world".into()];
+
+        let detector = ZeroShotIntentDetector::builder(KeywordEmbedder)
+            .add_intents(vec![greeting])
+            .add_intent("goodbye", vec!["bye".into()])
+            .with_scoring(ScoringStrategy::SoftmaxKnn {
+                k: 2,
+                temperature: 0.1,
+            })
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let scores = detector.get_intent_scores("hello world").unwrap();
+        assert!((scores.iter().map(|s| s.score).sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(scores.iter().all(|s| s.score >= 0.0));
+    }
+
+    struct CountingEmbedder {
+        model: &'static str,
+        embedded: Mutex<Vec<String>>,
+    }
+
+    impl CountingEmbedder {
+        fn new(model: &'static str) -> Self {
+            Self {
+                model,
+                embedded: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl EmbeddingModel for CountingEmbedder {
+        fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+            self.embedded.lock().unwrap().extend_from_slice(documents);
+            KeywordEmbedder.embed(documents)
         }
 
-        Ok(scores)
+        fn model_id(&self) -> Option<&str> {
+            Some(self.model)
+        }
     }
-}
 
-fn get_default_intents() -> Vec<ZeroShotIntent> {
-    let intents = vec![
-        ZeroShotIntent {
-            intent: "greeting".to_string(),
-            training_phrases: vec![
-                "hello".to_string(),
-                "hi".to_string(),
-                "how are you".to_string(),
-                "how are you doing".to_string(),
-                "how are you today".to_string(),
-                "hey how's it hanging".to_string(),
-                "hey how's it going".to_string(),
-                "hey how's it going today".to_string(),
-                "hello, nice to meet you".to_string(),
-                "hi, nice to meet you".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "goodbye".to_string(),
-            training_phrases: vec![
-                "goodbye".to_string(),
-                "bye".to_string(),
-                "see you later".to_string(),
-                "see you soon".to_string(),
-                "see you".to_string(),
-                "talk to you later".to_string(),
-                "talk to you soon".to_string(),
-                "talk to you".to_string(),
-                "have a good day".to_string(),
-                "have a good one".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "search_web".to_string(),
-            training_phrases: vec![
-                "look up how long the wait is at Il Mercato?".to_string(),
-                "What's the weather like tomorrow in Paris?".to_string(),
-                "Look up the quickest route to Oregon".to_string(),
-                "Search the web for trees".to_string(),
-                "Google the best restaurants in San Francisco".to_string(),
-                "Search the web for the best restaurants in San Francisco".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "search_files".to_string(),
-            training_phrases: vec![
-                "Search for my resume".to_string(),
-                "find any notes from my last meeting".to_string(),
-                "search for my notes from my last meeting".to_string(),
-                "find any references I have to semantic search".to_string(),
-                "search for my references to semantic search".to_string(),
-                "Do I have any files related to graph databases".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "casual_chat".to_string(),
-            training_phrases: vec![
-                "What's your favorite color?".to_string(),
-                "What do you think about the philosophy of existentialism?".to_string(),
-                "What are some good movies?".to_string(),
-                "Are you a robot?".to_string(),
-                "I wonder if I'll ever be able to pass the Turing test".to_string(),
-                "I've been thinking about the meaning of life lately".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "priming_task".to_string(),
-            training_phrases: vec![
-                "Help me write a letter".to_string(),
-                "Be polite to the customer".to_string(),
-                "You are a robot and you are going to help me".to_string(),
-                "Summarize the following text".to_string(),
-                "Jot down some notes".to_string(),
-                "Execute the following code".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "code_execution".to_string(),
-            training_phrases: vec![
-                "Tell me how many files are in the current directory".to_string(),
-                "What is the total size of the home directory?".to_string(),
-                "Give me a word count of the file named test.txt".to_string(),
-                "Execute a speed test on the network".to_string(),
-                "Show me the top 10 processes by memory usage".to_string(),
-                "Show me the top 10 processes by CPU usage".to_string(),
-            ],
-        },
-        ZeroShotIntent {
-            intent: "style_change".to_string(),
-            training_phrases: vec![
-                "Speak more casually".to_string(),
-                "Answer with more formality".to_string(),
-                "Speak more professionally".to_string(),
-                "Answer more rudely".to_string(),
-                "Write like a pirate".to_string(),
-                "Write like a robot".to_string(),
-                "Answer like a 1950s gangster".to_string(),
-                "Frame everything like a fortune cookie".to_string(),
-                "Respond in the form of a haiku".to_string(),
-                "Be more poetic".to_string(),
-                "Be more sarcastic".to_string(),
-            ],
-        },
-    ];
-
-    intents
+    #[test]
+    fn test_save_and_reload() {
+        let dir = std::env::temp_dir().join(format!("intent-embeddings-{}", std::process::id()));
+        let path = dir.join("intents.json");
+
+        let detector = ZeroShotIntentDetector::builder(CountingEmbedder::new("keywords"))
+            .add_intent("greeting", vec!["hello".into(), "hello there".into()])
+            .add_intent("goodbye", vec!["bye".into()])
+            .build()
+            .unwrap();
+        detector.save(&path).unwrap();
+
+        let loaded =
+            ZeroShotIntentDetector::load(CountingEmbedder::new("keywords"), &path).unwrap();
+        assert!(loaded.embedder.embedded.lock().unwrap().is_empty());
+        assert_eq!(loaded.intents[0].embeddings, detector.intents[0].embeddings);
+        assert_eq!(loaded.detect_intent("bye").unwrap().intent, "goodbye");
+
+        let rebuilt = ZeroShotIntentDetector::builder(CountingEmbedder::new("keywords"))
+            .with_saved_embeddings(&path)
+            .unwrap()
+            .add_intent("greeting", vec!["hello".into(), "hello world".into()])
+            .add_intent("goodbye", vec!["bye".into()])
+            .build()
+            .unwrap();
+        assert_eq!(
+            *rebuilt.embedder.embedded.lock().unwrap(),
+            vec!["hello world".to_string()]
+        );
+
+        let err = ZeroShotIntentDetector::load(CountingEmbedder::new("other"), &path)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "intent embeddings were made with model keywords, not other"
+        );
+
+        let (builder, ignored) = ZeroShotIntentDetector::builder(CountingEmbedder::new("other"))
+            .with_cached_embeddings(&path);
+        assert_eq!(
+            ignored.unwrap().to_string(),
+            "intent embeddings were made with model keywords, not other"
+        );
+        let other = builder
+            .add_intent("goodbye", vec!["bye".into()])
+            .build()
+            .unwrap();
+        assert_eq!(
+            *other.embedder.embedded.lock().unwrap(),
+            vec!["bye".to_string()]
+        );
+
+        std::fs::write(&path, "not json").unwrap();
+        let (builder, ignored) = ZeroShotIntentDetector::builder(CountingEmbedder::new("keywords"))
+            .with_cached_embeddings(&path);
+        assert!(ignored.is_some());
+        assert!(builder
+            .add_intent("goodbye", vec!["bye".into()])
+            .build()
+            .is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
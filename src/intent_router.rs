@@ -1,12 +1,27 @@
 use std::collections::HashMap;
 
-use crate::intent_detector::intent_detector::IntentDetector;
+use crate::intent_detector::intent_detector::{IntentDetector, IntentOutcome};
 use crate::model_traits::Responder;
+use crate::response::{keys, Response};
+
+/// Where the router sends input without a clear intent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fallback {
+    #[default]
+    DefaultRoute,
+    /// Ask the user what they meant instead of answering.
+    Clarify,
+}
+
+type RouteGuard = dyn Fn(&str) -> bool + Send;
 
 pub struct IntentRouter {
     detector: Box<dyn IntentDetector>,
     routes: HashMap<String, Box<dyn Responder>>,
+    guards: HashMap<String, Box<RouteGuard>>,
     default_route: Option<Box<dyn Responder>>,
+    unknown_fallback: Fallback,
+    ambiguous_fallback: Fallback,
 }
 
 impl IntentRouter {
@@ -14,7 +29,10 @@ impl IntentRouter {
         Self {
             detector,
             routes: HashMap::new(),
+            guards: HashMap::new(),
             default_route: None,
+            unknown_fallback: Fallback::default(),
+            ambiguous_fallback: Fallback::default(),
         }
     }
 
@@ -22,29 +40,175 @@ impl IntentRouter {
         self.routes.insert(intent, responder);
     }
 
+    /// Like `add_route`, but input that `guard` rejects goes to the default route
+    /// even when it has this intent.
+    pub fn add_guarded_route<G>(&mut self, intent: String, guard: G, responder: Box<dyn Responder>)
+    where
+        G: Fn(&str) -> bool + Send + 'static,
+    {
+        self.guards.insert(intent.clone(), Box::new(guard));
+        self.routes.insert(intent, responder);
+    }
+
     pub fn set_default_route(&mut self, responder: Box<dyn Responder>) {
         self.default_route = Some(responder);
     }
 
-    pub fn route(&mut self, input: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let intent = self.detector.detect_intent(input)?;
+    /// Where to send input that no intent scores high enough for.
+    pub fn set_unknown_fallback(&mut self, fallback: Fallback) {
+        self.unknown_fallback = fallback;
+    }
 
-        println!("Intent: {}", intent.intent);
+    /// Where to send input that several intents score about equally for.
+    pub fn set_ambiguous_fallback(&mut self, fallback: Fallback) {
+        self.ambiguous_fallback = fallback;
+    }
 
-        let responder = match self.routes.get_mut(&intent.intent) {
-            Some(responder) => responder,
-            None => match &mut self.default_route {
-                Some(responder) => responder,
-                None => return Err("No route found".into()),
-            },
+    pub fn route(&mut self, input: &str) -> Result<Response, Box<dyn std::error::Error>> {
+        let outcome = self.detector.classify(input)?;
+
+        let (intent, fallback) = match &outcome {
+            IntentOutcome::Detected(intent) => (Some(intent.intent.as_str()), None),
+            IntentOutcome::Unknown(_) => (None, Some(self.unknown_fallback)),
+            IntentOutcome::Ambiguous(_) => (None, Some(self.ambiguous_fallback)),
         };
 
-        responder.respond(input)
+        let response = match fallback {
+            Some(Fallback::Clarify) => Response::new(&clarification(&outcome)),
+            _ => {
+                let guards = &self.guards;
+                let route = intent
+                    .filter(|intent| guards.get(*intent).is_none_or(|guard| guard(input)))
+                    .and_then(|intent| self.routes.get_mut(intent));
+                let responder = match route.or(self.default_route.as_mut()) {
+                    Some(responder) => responder,
+                    None => return Err("No route found".into()),
+                };
+                responder.respond(input)?
+            }
+        };
+
+        let response = match &outcome {
+            IntentOutcome::Detected(intent) => {
+                response.with_metadata(keys::INTENT, intent.intent.as_str())
+            }
+            IntentOutcome::Unknown(_) => response.with_metadata(keys::INTENT, "unknown"),
+            IntentOutcome::Ambiguous(candidates) => response
+                .with_metadata(keys::INTENT, "ambiguous")
+                .with_metadata(
+                    keys::INTENT_CANDIDATES,
+                    candidates
+                        .iter()
+                        .map(|candidate| candidate.intent.clone())
+                        .collect::<Vec<_>>(),
+                ),
+        };
+
+        Ok(match outcome.best() {
+            Some(best) => response.with_metadata(keys::INTENT_SCORE, best.score),
+            None => response,
+        })
+    }
+}
+
+/// A question asking the user what they meant.
+fn clarification(outcome: &IntentOutcome) -> String {
+    let describe = |intent: &str| intent.replace('_', " ");
+
+    match outcome {
+        IntentOutcome::Ambiguous(candidates) => {
+            let mut options: Vec<String> = candidates
+                .iter()
+                .map(|candidate| describe(&candidate.intent))
+                .collect();
+            let last = options.pop().unwrap_or_default();
+            match options.is_empty() {
+                true => format!("Did you mean {}?", last),
+                false => format!("Did you mean {} or {}?", options.join(", "), last),
+            }
+        }
+        _ => "Sorry, I'm not sure what you're asking for. Could you rephrase that?".to_string(),
     }
 }
 
 impl Responder for IntentRouter {
-    fn respond(&mut self, input: &str) -> Result<String, Box<dyn std::error::Error>> {
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn std::error::Error>> {
         self.route(input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intent_detector::intent_detector::{ConfidencePolicy, IntentResult};
+    use crate::response::{MetadataValue, TextAdapter};
+    use std::error::Error;
+
+    struct FixedDetector;
+
+    impl IntentDetector for FixedDetector {
+        fn get_intent_scores(&self, text: &str) -> Result<Vec<IntentResult>, Box<dyn Error>> {
+            let score = |intent: &str| IntentResult {
+                intent: intent.to_string(),
+                score: if text.contains(intent) { 0.9 } else { 0.1 },
+            };
+            Ok(vec![score("search_web"), score("search_files")])
+        }
+
+        fn classify(&self, text: &str) -> Result<IntentOutcome, Box<dyn Error>> {
+            let policy = ConfidencePolicy::new()
+                .with_min_score(0.5)
+                .with_min_margin(0.05);
+            Ok(policy.classify(self.get_intent_scores(text)?))
+        }
+    }
+
+    fn echo(name: &'static str) -> Box<dyn Responder> {
+        Box::new(TextAdapter(move |_: &str| Ok(name.to_string())))
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let mut router = IntentRouter::new(Box::new(FixedDetector));
+        router.add_route("search_web".into(), echo("web"));
+        router.add_route("search_files".into(), echo("files"));
+        router.set_default_route(echo("chat"));
+        router.set_ambiguous_fallback(Fallback::Clarify);
+
+        assert_eq!(router.route("search_web please").unwrap().text, "web");
+
+        let unknown = router.route("asdfgh").unwrap();
+        assert_eq!(unknown.text, "chat");
+        assert_eq!(
+            unknown.metadata(keys::INTENT),
+            Some(&MetadataValue::from("unknown"))
+        );
+
+        let ambiguous = router.route("search_web or search_files").unwrap();
+        assert_eq!(ambiguous.text, "Did you mean search web or search files?");
+        assert_eq!(
+            ambiguous.metadata(keys::INTENT_CANDIDATES),
+            Some(&MetadataValue::from(vec!["search_web", "search_files"]))
+        );
+    }
+
+    #[test]
+    fn test_guarded_route() {
+        let mut router = IntentRouter::new(Box::new(FixedDetector));
+        router.add_guarded_route(
+            "search_files".into(),
+            |input: &str| input.contains('/'),
+            echo("files"),
+        );
+        router.set_default_route(echo("chat"));
+
+        assert_eq!(router.route("search_files in /tmp").unwrap().text, "files");
+
+        let response = router.route("search_files somewhere").unwrap();
+        assert_eq!(response.text, "chat");
+        assert_eq!(
+            response.metadata(keys::INTENT),
+            Some(&MetadataValue::from("search_files"))
+        );
+    }
+}
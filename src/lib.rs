@@ -1,8 +1,21 @@
 pub mod chatbot;
+pub mod code;
+pub mod config;
+pub mod conversation;
+pub mod credentials;
+pub mod insertion;
 pub mod intent_detector;
 pub mod intent_router;
 pub mod macros;
+pub mod memory;
+pub mod middleware;
 pub mod model_traits;
 pub mod openai;
 pub mod prebuilt;
+pub mod response;
+pub mod session;
 pub mod similarity;
+pub mod structured;
+pub mod style;
+pub mod template;
+pub mod tokens;
@@ -1,14 +1,22 @@
+use std::error::Error;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use assistant::chatbot::Chatbot;
+use assistant::code::approval::ApprovalPolicy;
+use assistant::credentials::providers::{CommandProvider, KeyFileProvider};
+use assistant::credentials::rotation::RotationStrategy;
+use assistant::credentials::{default_provider_chain, resolve_credentials, CredentialProvider};
 use assistant::intent_detector::intent_detector::IntentDetector;
-use assistant::intent_detector::zeroshot::ZeroShotIntentDetector;
 use assistant::model_traits::Responder;
 use assistant::openai::completion::client::CompletionClient;
 use assistant::openai::completion::config::ModelConfigurationBuilder;
-use assistant::openai::embedding::client::EmbeddingClient;
-use assistant::openai::embedding::config::EmbeddingModelConfig;
-use assistant::prebuilt::build_default_router;
+use assistant::prebuilt::{
+    build_cli_approval_policy, build_default_intent_detector, build_default_router,
+};
+use assistant::response::{keys, Attachment, Response};
+use assistant::session::store::{JsonFileStore, SessionStore};
 use clap::Parser;
 
 #[allow(dead_code)]
@@ -17,24 +25,134 @@ use clap::Parser;
 struct Cli {
     #[clap(short, long, default_value = "false")]
     intent: bool,
+
+    /// Read API keys from this file, one per line or comma separated
+    #[clap(long)]
+    key_file: Option<String>,
+
+    /// Read API keys from the output of this shell command
+    #[clap(long)]
+    key_command: Option<String>,
+
+    /// Rotate between keys by remaining quota, allowing this many tokens per key
+    #[clap(long)]
+    key_quota: Option<u64>,
+
+    /// Resume (or start) the chat session with this id, saving after every response
+    #[clap(long)]
+    session: Option<String>,
+
+    /// List saved chat sessions and exit
+    #[clap(long, default_value = "false")]
+    list_sessions: bool,
+
+    /// Delete the saved chat session with this id and exit
+    #[clap(long)]
+    delete_session: Option<String>,
+
+    /// Show generated code instead of running it
+    #[clap(long, default_value = "false")]
+    dry_run: bool,
+
+    /// Run generated code without asking if it only uses allowlisted commands and modules
+    #[clap(long, default_value = "false")]
+    auto_approve: bool,
+
+    /// Show the raw output of generated code instead of an answer written from it
+    #[clap(long, default_value = "false")]
+    no_synthesis: bool,
+
+    /// Load intents from this JSON, YAML or TOML file instead of the bundled ones
+    #[clap(long)]
+    intents: Option<PathBuf>,
 }
 
 fn main() {
     let args = Cli::parse();
 
+    if args.list_sessions || args.delete_session.is_some() {
+        if let Err(err) = manage_sessions(&args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let credentials = match load_credentials(&args) {
+        Ok(credentials) => credentials,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
     match args.intent {
-        false => run_router(),
-        true => run_intent_detector(),
+        false => {
+            let approval = build_cli_approval_policy(args.dry_run, args.auto_approve);
+            run_router(
+                credentials,
+                args.session.as_deref(),
+                approval,
+                !args.no_synthesis,
+                args.intents.as_deref(),
+            )
+        }
+        true => run_intent_detector(credentials, args.intents.as_deref()),
     }
 }
 
-fn run_router() {
-    let mut router = build_default_router();
+fn load_credentials(args: &Cli) -> Result<Arc<dyn CredentialProvider>, Box<dyn Error>> {
+    let provider: Box<dyn CredentialProvider> = match (&args.key_file, &args.key_command) {
+        (Some(path), _) => Box::new(KeyFileProvider::new(path)),
+        (None, Some(command)) => Box::new(CommandProvider::new(command)),
+        (None, None) => Box::new(default_provider_chain()),
+    };
+
+    let strategy = match args.key_quota {
+        Some(quota) => RotationStrategy::QuotaAware { quota },
+        None => RotationStrategy::RoundRobin,
+    };
+
+    resolve_credentials(provider.as_ref(), strategy)
+}
+
+fn manage_sessions(args: &Cli) -> Result<(), Box<dyn Error>> {
+    let store = JsonFileStore::default_location();
+
+    if let Some(id) = &args.delete_session {
+        store.delete(id)?;
+        println!("Deleted session {}", id);
+    }
+
+    if args.list_sessions {
+        for id in store.list()? {
+            println!("{}", id);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_router(
+    credentials: Arc<dyn CredentialProvider>,
+    session: Option<&str>,
+    approval: ApprovalPolicy,
+    synthesize: bool,
+    intents: Option<&Path>,
+) {
+    let mut router = match build_default_router(credentials, session, approval, synthesize, intents)
+    {
+        Ok(router) => router,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
     run_conversation_loop(&mut router);
 }
 
-fn run_chatbot() {
-    let api_key = std::env::var("OPENAI_KEY").unwrap();
+#[allow(dead_code)]
+fn run_chatbot(credentials: Arc<dyn CredentialProvider>) {
     let config = ModelConfigurationBuilder::default()
         .model("text-davinci-003".into())
         .max_tokens(1000)
@@ -43,13 +161,15 @@ fn run_chatbot() {
         .build()
         .unwrap();
 
-    let client = CompletionClient::new(api_key, config);
-    let mut chatbot = Chatbot::builder(client).prefix("You are a chatbot. Respond to the user, but respond as if you are a pirate. Really embellish, and be very very pirate-like. \n").build();
+    let client = CompletionClient::with_credentials(credentials, config);
+    let mut chatbot = Chatbot::builder(client).prefix("You are a chatbot. Respond to the user, but respond as if you are a pirate. Really embellish, and be very very pirate-like. \n").build().unwrap();
 
     run_conversation_loop(&mut chatbot);
 }
 
 fn run_conversation_loop<R: Responder>(chatbot: &mut R) {
+    let mut last: Option<Response> = None;
+
     loop {
         let mut input = String::new();
 
@@ -57,22 +177,62 @@ fn run_conversation_loop<R: Responder>(chatbot: &mut R) {
         std::io::stdout().flush().unwrap();
 
         std::io::stdin().read_line(&mut input).unwrap();
-        let response = chatbot.respond(&input).unwrap();
+        if input.trim() == "/expand" {
+            match &last {
+                Some(response) => print_details(response),
+                None => println!("Nothing to expand yet."),
+            }
+            continue;
+        }
 
+        let response = match chatbot.respond(&input) {
+            Ok(response) => response,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                continue;
+            }
+        };
+        if let Some(intent) = response.metadata(keys::INTENT) {
+            println!("Intent: {}", intent);
+        }
         println!("Assistant: {}", response);
+        if let Some(warning) = response.metadata(keys::WARNING) {
+            eprintln!("Warning: {}", warning);
+        }
+        if !text_attachments(&response).is_empty() {
+            println!("(type /expand to see the details)");
+        }
+        last = Some(response);
     }
 }
 
-fn run_intent_detector() {
-    let embeddings_model = EmbeddingClient::new(
-        std::env::var("OPENAI_KEY").unwrap(),
-        EmbeddingModelConfig::default(),
-    );
+/// Attachments that are meant to be read, like code and raw output.
+fn text_attachments(response: &Response) -> Vec<&Attachment> {
+    response
+        .attachments
+        .iter()
+        .filter(|attachment| attachment.mime_type.starts_with("text/"))
+        .collect()
+}
+
+fn print_details(response: &Response) {
+    let attachments = text_attachments(response);
+    if attachments.is_empty() {
+        println!("No details for the last response.");
+    }
+    for attachment in attachments {
+        println!("--- {} ---\n{}", attachment.name, attachment.content);
+    }
+}
 
-    let mut intent_detector = ZeroShotIntentDetector::builder(embeddings_model)
-        .with_default_intents()
-        .expect("Failed to load default intents")
-        .build();
+fn run_intent_detector(credentials: Arc<dyn CredentialProvider>, intents: Option<&Path>) {
+    let mut intent_detector = match build_default_intent_detector(credentials, intents) {
+        Ok(detector) => detector,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
     run_intent_detection_loop(&mut intent_detector);
 }
@@ -85,8 +245,17 @@ fn run_intent_detection_loop<I: IntentDetector>(intent_detector: &mut I) {
         std::io::stdout().flush().unwrap();
 
         std::io::stdin().read_line(&mut input).unwrap();
-        let intent = intent_detector.detect_intent(&input).unwrap();
+        let outcome = match intent_detector.classify(&input) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                continue;
+            }
+        };
 
-        println!("Intent: {}", intent);
+        match outcome.best() {
+            Some(best) => println!("Intent: {}, Score: {}", outcome, best.score),
+            None => println!("Intent: {}", outcome),
+        }
     }
 }
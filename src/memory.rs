@@ -0,0 +1,25 @@
+pub mod summary;
+pub mod vector;
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// Memory that outlives the prompt: relevant past exchanges are recalled for
+/// each new input.
+pub trait LongTermMemory: Send {
+    /// Past exchanges relevant to `query`, most relevant first.
+    fn recall(&self, query: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    fn remember(&mut self, text: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// One memory shared by several chatbots.
+impl<M: LongTermMemory> LongTermMemory for Arc<Mutex<M>> {
+    fn recall(&self, query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.lock().unwrap().recall(query)
+    }
+
+    fn remember(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.lock().unwrap().remember(text)
+    }
+}
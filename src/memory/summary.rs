@@ -0,0 +1,115 @@
+use std::error::Error;
+
+use crate::model_traits::CompletionModel;
+
+pub const DEFAULT_SUMMARY_PROMPT: &str = "Progressively summarize the lines of conversation provided, adding onto the previous summary and returning a new summary. Keep names, facts and decisions.
+
+Current summary:
+{summary}
+
+New lines of conversation:
+{lines}
+
+New summary:";
+
+const DEFAULT_TRIGGER_RATIO: f32 = 0.75;
+
+/// A running summary of conversation that no longer fits in the prompt.
+///
+/// When a chatbot's history grows past `trigger_ratio` of its token budget, the
+/// oldest turns are evicted and folded into the summary, which is injected at
+/// the top of every prompt.
+pub struct SummaryMemory {
+    model: Box<dyn CompletionModel>,
+    prompt: String,
+    trigger_ratio: f32,
+    summary: Option<String>,
+}
+
+impl SummaryMemory {
+    pub fn new<M: CompletionModel + 'static>(model: M) -> Self {
+        Self {
+            model: Box::new(model),
+            prompt: DEFAULT_SUMMARY_PROMPT.to_string(),
+            trigger_ratio: DEFAULT_TRIGGER_RATIO,
+            summary: None,
+        }
+    }
+
+    /// The summarization prompt. `{summary}` is replaced with the current summary
+    /// and `{lines}` with the evicted transcript.
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    /// The fraction (0 to 1) of the history budget at which summarization starts.
+    pub fn with_trigger_ratio(mut self, ratio: f32) -> Self {
+        self.trigger_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn trigger_ratio(&self) -> f32 {
+        self.trigger_ratio
+    }
+
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    pub fn set_summary(&mut self, summary: Option<String>) {
+        self.summary = summary;
+    }
+
+    pub fn clear(&mut self) {
+        self.summary = None;
+    }
+
+    /// Folds `lines` of transcript into the running summary.
+    pub fn summarize(&mut self, lines: &str) -> Result<(), Box<dyn Error>> {
+        let prompt = self
+            .prompt
+            .replace("{summary}", self.summary.as_deref().unwrap_or("(none)"))
+            .replace("{lines}", lines);
+
+        let summary = self.model.complete(&prompt)?.trim().to_string();
+        if !summary.is_empty() {
+            self.summary = Some(summary);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct EchoLinesModel;
+
+    impl CompletionModel for EchoLinesModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            let current = prompt
+                .split("Current: ")
+                .nth(1)
+                .unwrap()
+                .split('|')
+                .next()
+                .unwrap();
+            let lines = prompt.split("Lines: ").nth(1).unwrap();
+            Ok(format!("{} + {}", current, lines))
+        }
+    }
+
+    #[test]
+    fn test_summarize_builds_on_previous_summary() {
+        let mut memory =
+            SummaryMemory::new(EchoLinesModel).with_prompt("Current: {summary}|Lines: {lines}");
+
+        memory.summarize("first").unwrap();
+        assert_eq!(memory.summary(), Some("(none) + first"));
+
+        memory.summarize("second").unwrap();
+        assert_eq!(memory.summary(), Some("(none) + first + second"));
+    }
+}
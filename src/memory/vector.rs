@@ -0,0 +1,243 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::LongTermMemory;
+use crate::model_traits::EmbeddingModel;
+use crate::similarity::cosine_similarity;
+
+const DEFAULT_TOP_K: usize = 3;
+const DEFAULT_MIN_RELEVANCE: f32 = 0.8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryEntry {
+    pub text: String,
+    pub embedding: Vec<f32>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Long-term memory backed by embeddings.
+///
+/// Each remembered exchange is embedded and stored. Recall embeds the query and
+/// returns the `top_k` entries whose cosine similarity is at least
+/// `min_relevance`, ranked by similarity weighted by recency.
+pub struct VectorMemory<E: EmbeddingModel> {
+    embedder: E,
+    entries: Vec<MemoryEntry>,
+    top_k: usize,
+    min_relevance: f32,
+    recency_half_life: Option<u64>,
+    path: Option<PathBuf>,
+}
+
+impl<E: EmbeddingModel> VectorMemory<E> {
+    pub fn new(embedder: E) -> Self {
+        Self {
+            embedder,
+            entries: Vec::new(),
+            top_k: DEFAULT_TOP_K,
+            min_relevance: DEFAULT_MIN_RELEVANCE,
+            recency_half_life: None,
+            path: None,
+        }
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// The minimum cosine similarity for an entry to be recalled.
+    pub fn with_min_relevance(mut self, min_relevance: f32) -> Self {
+        self.min_relevance = min_relevance;
+        self
+    }
+
+    /// Halves an entry's ranking score for every `seconds` of age.
+    pub fn with_recency_half_life(mut self, seconds: u64) -> Self {
+        self.recency_half_life = Some(seconds);
+        self
+    }
+
+    /// Loads entries from `path` if it exists, and saves to it after every new entry.
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.into();
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => self.entries = serde_json::from_str(&contents)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        self.path = Some(path);
+        Ok(self)
+    }
+
+    pub fn entries(&self) -> &[MemoryEntry] {
+        &self.entries
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Replace the file in one step so a crash mid-write can't lose every memory.
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string(&self.entries)?)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn recency_weight(&self, timestamp: u64, now: u64) -> f32 {
+        match self.recency_half_life {
+            Some(half_life) if half_life > 0 => {
+                let age = now.saturating_sub(timestamp) as f32;
+                0.5f32.powf(age / half_life as f32)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Scores every entry against `embedding`, returning the relevant ones best first.
+    pub fn search(&self, embedding: &[f32]) -> Vec<(f32, &MemoryEntry)> {
+        let now = now();
+
+        let mut scored: Vec<(f32, &MemoryEntry)> = self
+            .entries
+            .iter()
+            .map(|entry| (cosine_similarity(embedding, &entry.embedding), entry))
+            .filter(|(similarity, _)| *similarity >= self.min_relevance)
+            .map(|(similarity, entry)| {
+                (
+                    similarity * self.recency_weight(entry.timestamp, now),
+                    entry,
+                )
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        scored.truncate(self.top_k);
+        scored
+    }
+}
+
+impl<E: EmbeddingModel> LongTermMemory for VectorMemory<E> {
+    fn recall(&self, query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.entries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let embedding = self.embedder.embed_question(query.to_string())?;
+
+        Ok(self
+            .search(&embedding)
+            .into_iter()
+            .map(|(_, entry)| entry.text.clone())
+            .collect())
+    }
+
+    fn remember(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let embedding = self
+            .embedder
+            .embed_answer(&[text.to_string()])?
+            .into_iter()
+            .next()
+            .ok_or("Embedding model returned no embedding")?;
+
+        self.entries.push(MemoryEntry {
+            text: text.to_string(),
+            embedding,
+            timestamp: now(),
+        });
+
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Embeds text as counts of a few marker words.
+    struct KeywordEmbedder;
+
+    impl EmbeddingModel for KeywordEmbedder {
+        fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+            Ok(documents
+                .iter()
+                .map(|document| {
+                    ["dog", "birthday", "rust"]
+                        .iter()
+                        .map(|word| document.matches(word).count() as f32 + 0.01)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_recall_relevant_entries() {
+        let mut memory = VectorMemory::new(KeywordEmbedder).with_top_k(1);
+
+        memory.remember("User: my dog is called Rex").unwrap();
+        memory.remember("User: my birthday is in May").unwrap();
+
+        assert_eq!(
+            memory.recall("when is my birthday?").unwrap(),
+            vec!["User: my birthday is in May"]
+        );
+        assert!(memory.recall("tell me about rust").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recency_weighting() {
+        let mut memory = VectorMemory::new(KeywordEmbedder).with_recency_half_life(60);
+
+        memory.entries = vec![
+            MemoryEntry {
+                text: "old".to_string(),
+                embedding: vec![1.0, 0.0, 0.0],
+                timestamp: now() - 600,
+            },
+            MemoryEntry {
+                text: "new".to_string(),
+                embedding: vec![0.9, 0.1, 0.0],
+                timestamp: now(),
+            },
+        ];
+
+        let results = memory.search(&[1.0, 0.0, 0.0]);
+        assert_eq!(results[0].1.text, "new");
+        assert_eq!(results[1].1.text, "old");
+    }
+
+    #[test]
+    fn test_persists_to_file() {
+        let path =
+            std::env::temp_dir().join(format!("assistant-memory-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut memory = VectorMemory::new(KeywordEmbedder).with_file(&path).unwrap();
+        memory.remember("my dog is called Rex").unwrap();
+
+        let reloaded = VectorMemory::new(KeywordEmbedder).with_file(&path).unwrap();
+        assert_eq!(reloaded.entries(), memory.entries());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::conversation::Turn;
+use crate::response::Response;
+
+/// What a chatbot can see of its conversation while running middleware.
+pub struct ConversationContext<'a> {
+    pub history: &'a [Turn],
+    pub pinned: &'a [Turn],
+    pub summary: Option<&'a str>,
+    pub variables: &'a HashMap<String, String>,
+}
+
+/// The result of a middleware stage that runs before the model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flow {
+    /// Continue with this (possibly rewritten) input.
+    Continue(String),
+    /// Skip the model and reply with this text directly.
+    Reply(String),
+}
+
+/// A stage in a chatbot's middleware pipeline.
+///
+/// `before` runs on the input ahead of the model call, in the order stages were
+/// added, and may rewrite the input or short-circuit with a direct reply. `after`
+/// runs on the model's response in the same order and may rewrite its text or
+/// add metadata and attachments. Stages
+/// take `&mut self`, so they can carry state between calls, and must be `Send`.
+pub trait Middleware: Send {
+    fn before(
+        &mut self,
+        context: &ConversationContext,
+        input: String,
+    ) -> Result<Flow, Box<dyn Error>> {
+        let _ = context;
+        Ok(Flow::Continue(input))
+    }
+
+    fn after(
+        &mut self,
+        context: &ConversationContext,
+        input: &str,
+        output: Response,
+    ) -> Result<Response, Box<dyn Error>> {
+        let _ = (context, input);
+        Ok(output)
+    }
+}
+
+/// A `before` stage built from a closure.
+pub struct Before<F>(pub F);
+
+impl<F> Middleware for Before<F>
+where
+    F: FnMut(&ConversationContext, String) -> Result<Flow, Box<dyn Error>> + Send,
+{
+    fn before(
+        &mut self,
+        context: &ConversationContext,
+        input: String,
+    ) -> Result<Flow, Box<dyn Error>> {
+        (self.0)(context, input)
+    }
+}
+
+/// An `after` stage built from a closure.
+pub struct After<F>(pub F);
+
+impl<F> Middleware for After<F>
+where
+    F: FnMut(&ConversationContext, &str, Response) -> Result<Response, Box<dyn Error>> + Send,
+{
+    fn after(
+        &mut self,
+        context: &ConversationContext,
+        input: &str,
+        output: Response,
+    ) -> Result<Response, Box<dyn Error>> {
+        (self.0)(context, input, output)
+    }
+}
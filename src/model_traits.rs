@@ -1,24 +1,185 @@
 use std::error::Error;
+use std::sync::Arc;
 
-pub trait Responder {
-    fn respond(&mut self, input: &str) -> Result<String, Box<dyn Error>>;
+use serde::de::DeserializeOwned;
+
+use crate::response::Response;
+use crate::structured::StructuredOutput;
+
+/// Anything that answers user input. Responders are `Send`, so a conversation can
+/// be served from any thread.
+pub trait Responder: Send {
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>>;
+}
+
+/// Tokens consumed by a model request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
 }
 
-pub trait CompletionModel {
+/// Candidate tokens and their log probabilities.
+pub type TokenLogprobs = Vec<(String, f32)>;
+
+/// Models are `Send + Sync`, so one model can be shared between chatbots with `Arc`.
+pub trait CompletionModel: Send + Sync {
     fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>>;
+
+    /// Completes `prompt`, stopping generation at any of `stop`.
+    fn complete_with_stop(&self, prompt: &str, stop: &[String]) -> Result<String, Box<dyn Error>> {
+        let _ = stop;
+        self.complete(prompt)
+    }
+
+    /// The `top` most likely first tokens of a completion of `prompt` and their log
+    /// probabilities, most likely first. `None` if the model doesn't report them, or
+    /// can't report that many.
+    fn next_token_logprobs(
+        &self,
+        prompt: &str,
+        top: usize,
+    ) -> Result<Option<TokenLogprobs>, Box<dyn Error>> {
+        let _ = (prompt, top);
+        Ok(None)
+    }
+
+    /// The number of tokens the model accepts for prompt and completion combined.
+    fn context_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// The maximum number of tokens the model will generate.
+    fn max_tokens(&self) -> Option<usize> {
+        None
+    }
+
+    /// Completes `prompt` like `complete_with_stop`, along with the token usage
+    /// reported for this request if the model tracks it.
+    fn complete_with_usage(
+        &self,
+        prompt: &str,
+        stop: &[String],
+    ) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        Ok((self.complete_with_stop(prompt, stop)?, None))
+    }
+
+    /// A serializable snapshot of the model's settings, used when saving sessions.
+    fn settings(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Restores settings previously returned by `settings`.
+    fn apply_settings(&mut self, settings: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        let _ = settings;
+        Ok(())
+    }
+
+    /// Completes `prompt` and parses the completion as `T`, re-asking on parse errors.
+    fn complete_as<T: DeserializeOwned>(
+        &self,
+        prompt: &str,
+        output: &StructuredOutput,
+    ) -> Result<T, Box<dyn Error>>
+    where
+        Self: Sized,
+    {
+        output.complete(self, prompt)
+    }
 }
 
-pub trait EmbeddingModel {
+/// A model that can fill in text between a prefix and a suffix.
+pub trait InsertionModel: Send + Sync {
+    fn insert(&self, prefix: &str, suffix: &str) -> Result<String, Box<dyn Error>>;
+}
+
+pub trait EmbeddingModel: Send + Sync {
     fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>>;
 
     fn embed_question(&self, text: String) -> Result<Vec<f32>, Box<dyn Error>> {
-        match self.embed(&vec![text]) {
+        match self.embed(&[text]) {
             Ok(embeddings) => Ok(embeddings.into_iter().next().unwrap()),
             Err(err) => Err(err),
         }
     }
 
-    fn embed_answer(&self, text: &Vec<String>) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    fn embed_answer(&self, text: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
         self.embed(text)
     }
+
+    /// The id of the model, so embeddings from different models aren't mixed.
+    fn model_id(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl<M: CompletionModel + ?Sized> CompletionModel for Arc<M> {
+    fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        (**self).complete(prompt)
+    }
+
+    fn complete_with_stop(&self, prompt: &str, stop: &[String]) -> Result<String, Box<dyn Error>> {
+        (**self).complete_with_stop(prompt, stop)
+    }
+
+    fn next_token_logprobs(
+        &self,
+        prompt: &str,
+        top: usize,
+    ) -> Result<Option<TokenLogprobs>, Box<dyn Error>> {
+        (**self).next_token_logprobs(prompt, top)
+    }
+
+    fn context_size(&self) -> Option<usize> {
+        (**self).context_size()
+    }
+
+    fn max_tokens(&self) -> Option<usize> {
+        (**self).max_tokens()
+    }
+
+    fn complete_with_usage(
+        &self,
+        prompt: &str,
+        stop: &[String],
+    ) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        (**self).complete_with_usage(prompt, stop)
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        (**self).settings()
+    }
+
+    /// Settings are only applied while the model is not shared; a shared model
+    /// keeps the settings it was built with.
+    fn apply_settings(&mut self, settings: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        match Arc::get_mut(self) {
+            Some(model) => model.apply_settings(settings),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<M: InsertionModel + ?Sized> InsertionModel for Arc<M> {
+    fn insert(&self, prefix: &str, suffix: &str) -> Result<String, Box<dyn Error>> {
+        (**self).insert(prefix, suffix)
+    }
+}
+
+impl<M: EmbeddingModel + ?Sized> EmbeddingModel for Arc<M> {
+    fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        (**self).embed(documents)
+    }
+
+    fn embed_question(&self, text: String) -> Result<Vec<f32>, Box<dyn Error>> {
+        (**self).embed_question(text)
+    }
+
+    fn embed_answer(&self, text: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        (**self).embed_answer(text)
+    }
+
+    fn model_id(&self) -> Option<&str> {
+        (**self).model_id()
+    }
 }
@@ -1,11 +1,15 @@
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
 use super::config::ModelConfiguration;
-use crate::model_traits::CompletionModel;
+use crate::credentials::{CredentialProvider, StaticKey};
+use crate::model_traits::{CompletionModel, InsertionModel, TokenLogprobs, TokenUsage};
 
 const URL: &str = "https://api.openai.com/v1/completions";
+const MAX_LOGPROBS: usize = 5;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct CompletionRequest {
@@ -25,9 +29,11 @@ impl CompletionRequest {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CompletionLogprobs {
-    pub token_logprobs: Vec<Vec<f32>>,
-    pub text_offset: Vec<Vec<u32>>,
-    pub top_logprobs: Vec<Vec<f32>>,
+    pub tokens: Vec<String>,
+    pub token_logprobs: Vec<Option<f32>>,
+    pub text_offset: Vec<u32>,
+    /// For each token, the most likely tokens at that position and their logprobs.
+    pub top_logprobs: Option<Vec<Option<HashMap<String, f32>>>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +44,14 @@ pub struct CompletionChoice {
     pub finish_reason: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionUsage {
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CompletionResponse {
     pub id: String,
@@ -45,26 +59,135 @@ pub struct CompletionResponse {
     pub created: u32,
     pub model: String,
     pub choices: Vec<CompletionChoice>,
+    pub usage: Option<CompletionUsage>,
 }
 
 pub struct CompletionClient {
-    api_key: String,
+    credentials: Arc<dyn CredentialProvider>,
     pub config: ModelConfiguration,
 }
 
 impl CompletionClient {
     pub fn new(api_key: String, config: ModelConfiguration) -> Self {
-        Self { api_key, config }
+        Self::with_credentials(Arc::new(StaticKey(api_key)), config)
+    }
+
+    pub fn with_credentials(
+        credentials: Arc<dyn CredentialProvider>,
+        config: ModelConfiguration,
+    ) -> Self {
+        Self {
+            credentials,
+            config,
+        }
+    }
+
+    fn send(&self, request: &CompletionRequest) -> Result<CompletionResponse, Box<dyn Error>> {
+        let api_key = self.credentials.api_key()?;
+        let result = send_completion_request(&api_key, request)?;
+
+        if let Some(usage) = &result.usage {
+            self.credentials.record_usage(&api_key, usage.total_tokens);
+        }
+
+        Ok(result)
     }
 }
 
 impl CompletionModel for CompletionClient {
-    fn complete(
+    fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        let request = CompletionRequest::new(prompt, self.config.clone());
+        let result = self.send(&request)?;
+        Ok(result.choices[0].text.clone())
+    }
+
+    fn complete_with_stop(&self, prompt: &str, stop: &[String]) -> Result<String, Box<dyn Error>> {
+        Ok(self.complete_with_usage(prompt, stop)?.0)
+    }
+
+    fn complete_with_usage(
         &self,
         prompt: &str,
-    ) -> Result<std::string::String, std::boxed::Box<(dyn std::error::Error + 'static)>> {
-        let request = CompletionRequest::new(prompt, self.config.clone());
-        let result = send_completion_request(&self.api_key, &request)?;
+        stop: &[String],
+    ) -> Result<(String, Option<TokenUsage>), Box<dyn Error>> {
+        let mut config = self.config.clone();
+        let sequences = config.stop.take().unwrap_or_default();
+        config.stop = Some(stop_sequences(sequences, stop));
+
+        let request = CompletionRequest::new(prompt, config);
+        let result = self.send(&request)?;
+        let usage = result.usage.as_ref().map(|usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        });
+        Ok((result.choices[0].text.clone(), usage))
+    }
+
+    fn next_token_logprobs(
+        &self,
+        prompt: &str,
+        top: usize,
+    ) -> Result<Option<TokenLogprobs>, Box<dyn Error>> {
+        // The API returns at most five alternatives per token.
+        if top > MAX_LOGPROBS {
+            return Ok(None);
+        }
+
+        let mut config = self.config.clone();
+        config.max_tokens = 1;
+        config.temperature = 0.0;
+        config.logprobs = Some(top.max(1) as u32);
+
+        let request = CompletionRequest::new(prompt, config);
+        let result = self.send(&request)?;
+        let top_logprobs = result
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.logprobs)
+            .and_then(|logprobs| logprobs.top_logprobs)
+            .and_then(|top_logprobs| top_logprobs.into_iter().next().flatten());
+
+        Ok(top_logprobs.map(|tokens| {
+            let mut tokens: TokenLogprobs = tokens.into_iter().collect();
+            tokens.sort_by(|a, b| b.1.total_cmp(&a.1));
+            tokens
+        }))
+    }
+
+    fn context_size(&self) -> Option<usize> {
+        Some(context_size(&self.config.model))
+    }
+
+    fn max_tokens(&self) -> Option<usize> {
+        Some(self.config.max_tokens as usize)
+    }
+
+    fn settings(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.config).ok()
+    }
+
+    fn apply_settings(&mut self, settings: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        self.config = serde_json::from_value(settings)?;
+        Ok(())
+    }
+}
+
+fn context_size(model: &str) -> usize {
+    match model {
+        "text-davinci-003" | "text-davinci-002" => 4097,
+        "code-davinci-002" => 8001,
+        _ => 2049,
+    }
+}
+
+impl InsertionModel for CompletionClient {
+    fn insert(&self, prefix: &str, suffix: &str) -> Result<String, Box<dyn Error>> {
+        let mut config = self.config.clone();
+        config.suffix = Some(suffix.to_string());
+
+        let request = CompletionRequest::new(prefix, config);
+        let result = self.send(&request)?;
         Ok(result.choices[0].text.clone())
     }
 }
@@ -75,8 +198,6 @@ fn send_completion_request(
 ) -> Result<CompletionResponse, reqwest::Error> {
     let client = reqwest::blocking::Client::new();
 
-    let request_json = serde_json::to_string(&request).unwrap();
-
     let response = client
         .post(URL)
         .header("Authorization", "Bearer ".to_string() + api_key)
@@ -93,8 +214,20 @@ fn send_completion_request(
     }
 }
 
+/// The configured stop sequences followed by `extra`, without duplicates. The API
+/// accepts at most four, so any beyond that are dropped.
+fn stop_sequences(mut sequences: Vec<String>, extra: &[String]) -> Vec<String> {
+    sequences.extend(extra.iter().cloned());
+    let mut seen = HashSet::new();
+    sequences.retain(|sequence| seen.insert(sequence.clone()));
+    sequences.truncate(4);
+    sequences
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{stop_sequences, CompletionClient};
+    use crate::model_traits::CompletionModel;
     use crate::openai::completion::config::ModelConfigurationBuilder;
 
     #[test]
@@ -107,4 +240,30 @@ mod tests {
 
         assert!(config.is_ok());
     }
+
+    #[test]
+    fn test_too_many_logprobs() {
+        let config = ModelConfigurationBuilder::default()
+            .model("text-davinci-003".into())
+            .max_tokens(2)
+            .temperature(0.0)
+            .build()
+            .unwrap();
+        let client = CompletionClient::new("sk-unused".into(), config);
+
+        assert!(client.next_token_logprobs("prompt", 9).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let strings = |sequences: &[&str]| -> Vec<String> {
+            sequences.iter().map(|s| s.to_string()).collect()
+        };
+
+        let sequences = stop_sequences(strings(&["\n", "Human:"]), &strings(&["AI:", "\n"]));
+        assert_eq!(sequences, strings(&["\n", "Human:", "AI:"]));
+
+        let sequences = stop_sequences(strings(&["a", "b", "a"]), &strings(&["c", "d", "e"]));
+        assert_eq!(sequences, strings(&["a", "b", "c", "d"]));
+    }
 }
@@ -1,3 +1,7 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::credentials::{CredentialProvider, StaticKey};
 use crate::model_traits::EmbeddingModel;
 use serde::{Deserialize, Serialize};
 
@@ -32,8 +36,8 @@ pub struct Embedding {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Usage {
-    prompt_tokens: u32,
-    total_tokens: u32,
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,30 +50,47 @@ pub struct EmbeddingResponse {
 }
 
 pub struct EmbeddingClient {
-    api_key: String,
+    credentials: Arc<dyn CredentialProvider>,
     pub config: EmbeddingModelConfig,
 }
 
 impl EmbeddingClient {
     pub fn new(api_key: String, config: EmbeddingModelConfig) -> Self {
-        Self { api_key, config }
+        Self::with_credentials(Arc::new(StaticKey(api_key)), config)
+    }
+
+    pub fn with_credentials(
+        credentials: Arc<dyn CredentialProvider>,
+        config: EmbeddingModelConfig,
+    ) -> Self {
+        Self {
+            credentials,
+            config,
+        }
     }
 }
 
 impl EmbeddingModel for EmbeddingClient {
-    fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+    fn embed(&self, documents: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let api_key = self.credentials.api_key()?;
         let client = reqwest::blocking::Client::new();
         let request = EmbeddingRequest::new(documents.to_vec(), self.config.clone());
 
         let response = client
             .post(URL)
-            .header("Authorization", "Bearer ".to_string() + &self.api_key)
+            .header("Authorization", "Bearer ".to_string() + &api_key)
             .json(&request)
             .send()?;
 
         let result: EmbeddingResponse = response.json()?;
+        self.credentials
+            .record_usage(&api_key, result.usage.total_tokens);
 
         let embeddings = result.data.into_iter().map(|x| x.embedding).collect();
         Ok(embeddings)
     }
+
+    fn model_id(&self) -> Option<&str> {
+        Some(&self.config.model)
+    }
 }
@@ -1,18 +1,82 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::Write;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::chatbot::Chatbot;
+use crate::code::approval::{Allowlist, ApprovalPolicy, CliApprover};
+use crate::code::executor::{network_isolation_supported, CodeExecutor};
+use crate::code::responder::CodeExecutionResponder;
+use crate::code::Language;
+use crate::config::config_dir;
+use crate::credentials::CredentialProvider;
+use crate::insertion::{InsertionResponder, DEFAULT_INSERTION_MARKER};
+use crate::intent_detector::intent_detector::{ConfidencePolicy, IntentDetector};
+use crate::intent_detector::llm::LlmIntentDetector;
+use crate::intent_detector::saved::phrase_hash;
 use crate::intent_detector::zeroshot::ZeroShotIntentDetector;
-use crate::intent_router::IntentRouter;
+use crate::intent_router::{Fallback, IntentRouter};
+use crate::memory::summary::SummaryMemory;
+use crate::memory::vector::VectorMemory;
+use crate::memory::LongTermMemory;
 use crate::openai::completion::client::CompletionClient;
 use crate::openai::completion::config::ModelConfigurationBuilder;
 use crate::openai::embedding::client::EmbeddingClient;
 use crate::openai::embedding::config::EmbeddingModelConfig;
+use crate::session::manager::SessionManager;
+use crate::session::store::{validate_session_id, JsonFileStore};
+use crate::style::{StyleHandle, StyleManager};
+use crate::template::{PromptTemplate, TemplateRegistry};
 
-pub fn build_main_chatbot() -> Chatbot<CompletionClient> {
-    let api_key = std::env::var("OPENAI_KEY").unwrap();
+pub const DEFAULT_PERSONA: &str =
+    "Respond as if you are a pirate. Really embellish, and be very very pirate-like.";
+
+/// The main conversational chatbot. Exchanges are remembered in
+/// `~/.config/assistant/memory.json` so they can be recalled in later sessions.
+/// Its style comes from `styles`, which a `StyleManager` can change at runtime.
+pub fn build_main_chatbot(
+    credentials: Arc<dyn CredentialProvider>,
+    styles: StyleHandle,
+) -> Result<Chatbot<CompletionClient>, Box<dyn Error>> {
+    let memory = build_long_term_memory(credentials.clone())?;
+    main_chatbot(credentials, styles, memory)
+}
+
+/// Exchanges remembered in `~/.config/assistant/memory.json`, if there is a config
+/// directory.
+pub fn build_long_term_memory(
+    credentials: Arc<dyn CredentialProvider>,
+) -> Result<VectorMemory<EmbeddingClient>, Box<dyn Error>> {
+    long_term_memory(credentials, config_dir().map(|dir| dir.join("memory.json")))
+}
+
+/// Exchanges from session `id` only, remembered in
+/// `~/.config/assistant/memory/<id>.json` if there is a config directory.
+pub fn build_session_memory(
+    credentials: Arc<dyn CredentialProvider>,
+    id: &str,
+) -> Result<VectorMemory<EmbeddingClient>, Box<dyn Error>> {
+    validate_session_id(id)?;
+    let path = config_dir().map(|dir| dir.join("memory").join(format!("{}.json", id)));
+    long_term_memory(credentials, path)
+}
+
+fn long_term_memory(
+    credentials: Arc<dyn CredentialProvider>,
+    path: Option<PathBuf>,
+) -> Result<VectorMemory<EmbeddingClient>, Box<dyn Error>> {
+    let embedder = EmbeddingClient::with_credentials(credentials, EmbeddingModelConfig::default());
+
+    match path {
+        Some(path) => VectorMemory::new(embedder).with_file(path),
+        None => Ok(VectorMemory::new(embedder)),
+    }
+}
+
+fn main_chatbot<M: LongTermMemory + 'static>(
+    credentials: Arc<dyn CredentialProvider>,
+    styles: StyleHandle,
+    memory: M,
+) -> Result<Chatbot<CompletionClient>, Box<dyn Error>> {
     let config = ModelConfigurationBuilder::default()
         .model("text-davinci-003".into())
         .max_tokens(1000)
@@ -21,69 +85,318 @@ pub fn build_main_chatbot() -> Chatbot<CompletionClient> {
         .build()
         .unwrap();
 
-    let client = CompletionClient::new(api_key, config);
+    let summary_config = ModelConfigurationBuilder::default()
+        .model("text-davinci-003".into())
+        .max_tokens(256)
+        .temperature(0.0)
+        .build()
+        .unwrap();
+
+    let client = CompletionClient::with_credentials(credentials.clone(), config);
+    let summarizer = CompletionClient::with_credentials(credentials, summary_config);
+
+    let mut builder = Chatbot::builder(client)
+        .summary_memory(SummaryMemory::new(summarizer))
+        .long_term_memory(memory)
+        .styles(styles)
+        .prefix("You are a chatbot. Respond to the user.\n");
 
-    Chatbot::builder(client).prefix("You are a chatbot. Respond to the user, but respond as if you are a pirate. Really embellish, and be very very pirate-like. \n").build()
+    // A `main` template in ~/.config/assistant/templates replaces the default layout,
+    // and may include any other template in that directory.
+    if let Some(dir) = config_dir().map(|dir| dir.join("templates")) {
+        if dir.join("main.txt").exists() {
+            builder = builder
+                .template(PromptTemplate::from_file(dir.join("main.txt"))?)
+                .template_registry(TemplateRegistry::load_dir(&dir)?);
+        }
+    }
+
+    builder.build()
+}
+
+/// Asks before running generated code, except in dry-run mode or for scripts that
+/// pass the default allowlist when `auto_approve` is set. Decisions are logged to
+/// `~/.config/assistant/audit.jsonl`.
+pub fn build_cli_approval_policy(dry_run: bool, auto_approve: bool) -> ApprovalPolicy {
+    let mut policy = ApprovalPolicy::new()
+        .with_approver(CliApprover)
+        .with_dry_run(dry_run);
+
+    if auto_approve {
+        policy = policy.with_allowlist(Allowlist::default());
+    }
+    if let Some(dir) = config_dir() {
+        policy = policy.with_audit_log(dir.join("audit.jsonl"));
+    }
+
+    policy
 }
 
-pub fn build_code_execution_chatbot() -> Chatbot<CompletionClient> {
-    let api_key = std::env::var("OPENAI_KEY").unwrap();
+/// Solves problems by generating and running bash, Python, Node or Rust code. A
+/// `code_<language>.txt` template in `~/.config/assistant/templates` (e.g.
+/// `code_bash.txt`) replaces that language's prompt. Code only runs once `approval`
+/// allows it, and if `synthesize` is set its output is turned into a
+/// natural-language answer. Each run gets a scratch directory as its working
+/// directory and `HOME`, and no network access where the system supports that.
+pub fn build_code_execution_responder(
+    credentials: Arc<dyn CredentialProvider>,
+    approval: ApprovalPolicy,
+    synthesize: bool,
+) -> Result<CodeExecutionResponder<CompletionClient>, Box<dyn Error>> {
     let config = ModelConfigurationBuilder::default()
         .model("text-davinci-003".into())
-        .max_tokens(256)
+        .max_tokens(512)
         .temperature(0.0)
         .top_p(1.0)
         .build()
         .unwrap();
 
-    let client = CompletionClient::new(api_key, config);
+    let synthesis_config = ModelConfigurationBuilder::default()
+        .model("text-davinci-003".into())
+        .max_tokens(256)
+        .temperature(0.3)
+        .build()
+        .unwrap();
+
+    let client = CompletionClient::with_credentials(credentials.clone(), config);
+    let mut responder = CodeExecutionResponder::new(client).with_approval(approval);
+    if network_isolation_supported() {
+        for language in Language::ALL {
+            let executor = CodeExecutor::for_language(language).with_network_isolation(true);
+            responder = responder.with_executor(language, executor);
+        }
+    }
+    if synthesize {
+        let synthesizer = CompletionClient::with_credentials(credentials, synthesis_config);
+        responder = responder.with_synthesizer(synthesizer);
+    }
+
+    if let Some(dir) = config_dir().map(|dir| dir.join("templates")) {
+        for language in Language::ALL {
+            let path = dir.join(format!("code_{}.txt", language.tag()));
+            if path.exists() {
+                responder = responder.with_prompt(language, PromptTemplate::from_file(path)?)?;
+            }
+        }
+    }
 
-    Chatbot::builder(client)
-        .prefix("Write a python script to solve the following problem: ")
-        .conversation_limit(0)
-        .add_postprocessor(&execute_code)
+    Ok(responder)
+}
+
+pub fn build_insertion_responder(
+    credentials: Arc<dyn CredentialProvider>,
+) -> InsertionResponder<CompletionClient> {
+    let config = ModelConfigurationBuilder::default()
+        .model("text-davinci-003".into())
+        .max_tokens(512)
+        .temperature(0.5)
+        .top_p(1.0)
         .build()
+        .unwrap();
+
+    let client = CompletionClient::with_credentials(credentials, config);
+
+    InsertionResponder::new(client)
 }
 
-fn execute_code(code: &str) -> Result<String, Box<dyn Error>> {
-    let mut file = File::create("tmpscript.py")?;
-    file.write_all(code.as_bytes())?;
+pub fn build_style_manager(
+    credentials: Arc<dyn CredentialProvider>,
+    styles: StyleHandle,
+) -> StyleManager<CompletionClient> {
+    let config = ModelConfigurationBuilder::default()
+        .model("text-davinci-003".into())
+        .max_tokens(64)
+        .temperature(0.0)
+        .stop(vec!["\n".to_string()])
+        .build()
+        .unwrap();
 
-    let output = match Command::new("python3").arg("tmpscript.py").output() {
-        Ok(_output) => match String::from_utf8(_output.stdout)?.trim() {
-            "" => "No output. Could be an issue with the code.".to_string(),
-            trimmed_output => trimmed_output.to_string(),
-        },
-        Err(err) => "Unable to execute code. Result: ".to_string() + &err.to_string(),
+    let client = CompletionClient::with_credentials(credentials, config);
+
+    StyleManager::new(client, styles)
+}
+
+/// Below this similarity to every training phrase, input has no known intent and
+/// goes to the main chatbot.
+const MIN_INTENT_SCORE: f32 = 0.8;
+/// Intents scoring closer than this to the best are asked about.
+const MIN_INTENT_MARGIN: f32 = 0.01;
+
+/// The LLM detector's scores are probabilities, so it has its own minimums.
+const MIN_LLM_INTENT_PROBABILITY: f32 = 0.5;
+const MIN_LLM_INTENT_MARGIN: f32 = 0.1;
+
+/// Builds the intent detector from an intents file, or the bundled intents if
+/// `intents` is `None`. Phrase embeddings are cached in `~/.config/assistant`, so
+/// only new phrases are embedded.
+pub fn build_default_intent_detector(
+    credentials: Arc<dyn CredentialProvider>,
+    intents: Option<&Path>,
+) -> Result<ZeroShotIntentDetector<EmbeddingClient>, Box<dyn Error>> {
+    let embeddings_model =
+        EmbeddingClient::with_credentials(credentials, EmbeddingModelConfig::default());
+
+    let cache = intent_embeddings_cache(intents);
+
+    let mut builder = ZeroShotIntentDetector::builder(embeddings_model).with_confidence(
+        ConfidencePolicy::new()
+            .with_min_score(MIN_INTENT_SCORE)
+            .with_min_margin(MIN_INTENT_MARGIN),
+    );
+    if let Some(path) = &cache {
+        let ignored;
+        (builder, ignored) = builder.with_cached_embeddings(path);
+        if let Some(err) = ignored {
+            eprintln!("Ignoring intent embeddings in {}: {}", path.display(), err);
+        }
+    }
+    let builder = match intents {
+        Some(path) => builder.with_intents_file(path)?,
+        None => builder.with_default_intents()?,
     };
 
-    // Remove the script file
-    std::fs::remove_file("tmpscript.py")?;
+    let detector = builder.build()?;
+    if let Some(path) = &cache {
+        if let Err(err) = detector.save(path) {
+            eprintln!(
+                "Unable to save intent embeddings to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+    Ok(detector)
+}
 
-    Ok(output)
+/// Where phrase embeddings are cached: `intent_embeddings.json` for the bundled
+/// intents, and a file of its own for each intents file, so switching between them
+/// doesn't embed every phrase again.
+fn intent_embeddings_cache(intents: Option<&Path>) -> Option<PathBuf> {
+    let name = match intents {
+        None => "intent_embeddings.json".to_string(),
+        Some(path) => {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            format!(
+                "intent_embeddings-{}.json",
+                phrase_hash(&path.to_string_lossy())
+            )
+        }
+    };
+    config_dir().map(|dir| dir.join(name))
 }
 
-pub fn build_default_intent_detector() -> ZeroShotIntentDetector<EmbeddingClient> {
-    let embeddings_model = EmbeddingClient::new(
-        std::env::var("OPENAI_KEY").unwrap(),
-        EmbeddingModelConfig::default(),
+/// An intent detector that asks a completion model to classify input, for intents
+/// with too few training phrases to match against. Intents come from an intents
+/// file, or the bundled intents if `intents` is `None`.
+pub fn build_llm_intent_detector(
+    credentials: Arc<dyn CredentialProvider>,
+    intents: Option<&Path>,
+) -> Result<LlmIntentDetector<CompletionClient>, Box<dyn Error>> {
+    let config = ModelConfigurationBuilder::default()
+        .model("text-davinci-003".into())
+        .max_tokens(2)
+        // Logprobs are always read at temperature 0; this is for sampling, which
+        // is used when there are too many intents for logprobs.
+        .temperature(0.7)
+        .build()
+        .unwrap();
+
+    let client = CompletionClient::with_credentials(credentials, config);
+
+    let detector = LlmIntentDetector::new(client).with_confidence(
+        ConfidencePolicy::new()
+            .with_min_score(MIN_LLM_INTENT_PROBABILITY)
+            .with_min_margin(MIN_LLM_INTENT_MARGIN),
     );
+    match intents {
+        Some(path) => detector.with_intents_file(path),
+        None => Ok(detector.with_default_intents()),
+    }
+}
+
+/// Builds the default router. If `session` is given, the main chatbot resumes and
+/// auto-saves that session in the default session store. `synthesize` turns code
+/// output into natural-language answers.
+pub fn build_default_router(
+    credentials: Arc<dyn CredentialProvider>,
+    session: Option<&str>,
+    approval: ApprovalPolicy,
+    synthesize: bool,
+    intents: Option<&Path>,
+) -> Result<IntentRouter, Box<dyn Error>> {
+    let detector = Box::new(build_default_intent_detector(credentials.clone(), intents)?);
+    let memory = build_long_term_memory(credentials.clone())?;
+    build_router(credentials, detector, memory, session, approval, synthesize)
+}
 
-    let intent_detector = ZeroShotIntentDetector::builder(embeddings_model)
-        .with_default_intents()
-        .expect("Failed to load default intents")
-        .build();
+/// Serves many concurrent conversations, each with its own default router. The
+/// intent detector is built once and shared by every session; conversations,
+/// summaries, styles and long-term memory (see `build_session_memory`) are per
+/// session. Sessions are resumed from and saved to the default session store.
+///
+/// `approval` builds the approval policy for each session from its id. Sessions
+/// usually have no terminal to ask on, so with a policy that has no approver, code
+/// that isn't auto-approved is denied.
+pub fn build_session_manager<A>(
+    credentials: Arc<dyn CredentialProvider>,
+    approval: A,
+    synthesize: bool,
+    intents: Option<&Path>,
+) -> Result<SessionManager, Box<dyn Error>>
+where
+    A: Fn(&str) -> ApprovalPolicy + Send + Sync + 'static,
+{
+    let detector = Arc::new(build_default_intent_detector(credentials.clone(), intents)?);
 
-    intent_detector
+    Ok(SessionManager::new(move |id| {
+        let memory = build_session_memory(credentials.clone(), id)?;
+        let router = build_router(
+            credentials.clone(),
+            Box::new(detector.clone()),
+            memory,
+            Some(id),
+            approval(id),
+            synthesize,
+        )?;
+        Ok(Box::new(router))
+    }))
 }
 
-pub fn build_default_router() -> IntentRouter {
-    let mut router = IntentRouter::new(Box::new(build_default_intent_detector()));
+fn build_router<M: LongTermMemory + 'static>(
+    credentials: Arc<dyn CredentialProvider>,
+    detector: Box<dyn IntentDetector>,
+    memory: M,
+    session: Option<&str>,
+    approval: ApprovalPolicy,
+    synthesize: bool,
+) -> Result<IntentRouter, Box<dyn Error>> {
+    let mut router = IntentRouter::new(detector);
     router.add_route(
         "code_execution".into(),
-        Box::new(build_code_execution_chatbot()),
+        Box::new(build_code_execution_responder(
+            credentials.clone(),
+            approval,
+            synthesize,
+        )?),
+    );
+    router.add_guarded_route(
+        "priming_task".into(),
+        |input: &str| input.contains(DEFAULT_INSERTION_MARKER),
+        Box::new(build_insertion_responder(credentials.clone())),
     );
-    router.set_default_route(Box::new(build_main_chatbot()));
 
-    router
+    let styles = StyleHandle::with_styles(vec![DEFAULT_PERSONA.to_string()]);
+    router.add_route(
+        "style_change".into(),
+        Box::new(build_style_manager(credentials.clone(), styles.clone())),
+    );
+
+    let mut main_chatbot = main_chatbot(credentials, styles, memory)?;
+    if let Some(id) = session {
+        main_chatbot.resume(id, JsonFileStore::default_location())?;
+    }
+    router.set_default_route(Box::new(main_chatbot));
+    router.set_ambiguous_fallback(Fallback::Clarify);
+
+    Ok(router)
 }
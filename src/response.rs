@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model_traits::Responder;
+
+/// Well-known metadata keys.
+pub mod keys {
+    /// The intent the router chose: `unknown` or `ambiguous` if it couldn't.
+    pub const INTENT: &str = "intent";
+    /// The detector's score for the chosen intent, or for the closest one if the
+    /// intent was unknown or ambiguous.
+    pub const INTENT_SCORE: &str = "intent_score";
+    /// The intents an ambiguous input could have been, best first.
+    pub const INTENT_CANDIDATES: &str = "intent_candidates";
+    pub const PROMPT_TOKENS: &str = "prompt_tokens";
+    pub const COMPLETION_TOKENS: &str = "completion_tokens";
+    /// Wall-clock time spent producing the response, in milliseconds.
+    pub const LATENCY_MS: &str = "latency_ms";
+    /// Where the information in the response came from.
+    pub const SOURCES: &str = "sources";
+    /// Whether generated code was run to produce the response.
+    pub const CODE_EXECUTED: &str = "code_executed";
+    /// How many times code was generated and run to produce the response.
+    pub const ATTEMPTS: &str = "attempts";
+    /// The exit code of executed code.
+    pub const EXIT_CODE: &str = "exit_code";
+    /// The language of extracted code.
+    pub const CODE_LANGUAGE: &str = "code_language";
+    /// The style instructions active after a style change, in the order they apply.
+    pub const STYLES: &str = "styles";
+    /// Something that went wrong after the response was made, such as the session
+    /// failing to save.
+    pub const WARNING: &str = "warning";
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<MetadataValue>),
+}
+
+impl Display for MetadataValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataValue::Bool(value) => write!(f, "{}", value),
+            MetadataValue::Integer(value) => write!(f, "{}", value),
+            MetadataValue::Float(value) => write!(f, "{}", value),
+            MetadataValue::String(value) => write!(f, "{}", value),
+            MetadataValue::List(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        MetadataValue::Bool(value)
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(value: i64) -> Self {
+        MetadataValue::Integer(value)
+    }
+}
+
+impl From<u32> for MetadataValue {
+    fn from(value: u32) -> Self {
+        MetadataValue::Integer(value as i64)
+    }
+}
+
+impl From<usize> for MetadataValue {
+    fn from(value: usize) -> Self {
+        MetadataValue::Integer(value as i64)
+    }
+}
+
+impl From<f32> for MetadataValue {
+    fn from(value: f32) -> Self {
+        MetadataValue::Float(value as f64)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(value: f64) -> Self {
+        MetadataValue::Float(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        MetadataValue::String(value.to_string())
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        MetadataValue::String(value)
+    }
+}
+
+impl<T: Into<MetadataValue>> From<Vec<T>> for MetadataValue {
+    fn from(values: Vec<T>) -> Self {
+        MetadataValue::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Supplementary content that goes with a response, such as generated code.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub mime_type: String,
+    pub content: String,
+}
+
+impl Attachment {
+    pub fn new(name: &str, mime_type: &str, content: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+/// A responder's reply: the text shown to the user, plus metadata and attachments.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub text: String,
+    pub metadata: HashMap<String, MetadataValue>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Response {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_metadata<V: Into<MetadataValue>>(mut self, key: &str, value: V) -> Self {
+        self.set_metadata(key, value);
+        self
+    }
+
+    pub fn set_metadata<V: Into<MetadataValue>>(&mut self, key: &str, value: V) {
+        self.metadata.insert(key.to_string(), value.into());
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<&MetadataValue> {
+        self.metadata.get(key)
+    }
+
+    pub fn with_attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn attachment(&self, name: &str) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.name == name)
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl From<String> for Response {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&str> for Response {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+/// A responder that only produces text.
+pub trait TextResponder: Send {
+    fn respond_text(&mut self, input: &str) -> Result<String, Box<dyn Error>>;
+}
+
+impl<F> TextResponder for F
+where
+    F: FnMut(&str) -> Result<String, Box<dyn Error>> + Send,
+{
+    fn respond_text(&mut self, input: &str) -> Result<String, Box<dyn Error>> {
+        self(input)
+    }
+}
+
+/// Adapts a `TextResponder` (including a plain closure) to `Responder`.
+pub struct TextAdapter<R: TextResponder>(pub R);
+
+impl<R: TextResponder> Responder for TextAdapter<R> {
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>> {
+        Ok(Response::from(self.0.respond_text(input)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_adapter() {
+        let mut responder = TextAdapter(|input: &str| Ok(input.to_uppercase()));
+
+        let response = responder.respond("ahoy").unwrap();
+        assert_eq!(response, Response::new("AHOY"));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let response = Response::new("42")
+            .with_metadata(keys::CODE_EXECUTED, true)
+            .with_metadata(keys::SOURCES, vec!["a.txt", "b.txt"])
+            .with_attachment(Attachment::new("code", "text/x-python", "print(42)"));
+
+        assert_eq!(response.to_string(), "42");
+        assert_eq!(
+            response.metadata(keys::CODE_EXECUTED),
+            Some(&MetadataValue::Bool(true))
+        );
+        assert_eq!(
+            response.metadata(keys::SOURCES).unwrap().to_string(),
+            "[a.txt, b.txt]"
+        );
+        assert_eq!(response.attachment("code").unwrap().content, "print(42)");
+
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response);
+    }
+}
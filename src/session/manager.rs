@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::model_traits::Responder;
+use crate::response::Response;
+
+type SessionFactory = dyn Fn(&str) -> Result<Box<dyn Responder>, Box<dyn Error>> + Send + Sync;
+type Session = Arc<Mutex<Box<dyn Responder>>>;
+
+/// Serves many conversations at once, each with its own responder.
+///
+/// Responders are created on first use by the factory, which is where shared pieces
+/// (models, intent embeddings, long-term memory) are handed out, typically as `Arc`s.
+/// Different sessions respond concurrently; inputs to the same session are handled
+/// one at a time.
+pub struct SessionManager {
+    factory: Box<SessionFactory>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl SessionManager {
+    /// `factory` builds the responder for a new session from its id.
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<Box<dyn Responder>, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        Self {
+            factory: Box::new(factory),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Responds to `input` in session `id`, starting the session if needed.
+    pub fn respond(&self, id: &str, input: &str) -> Result<Response, Box<dyn Error>> {
+        let session = self.session(id)?;
+        let mut responder = session
+            .lock()
+            .map_err(|_| format!("Session '{}' is unusable after a panic", id))?;
+
+        responder.respond(input)
+    }
+
+    /// The ids of the active sessions, sorted.
+    pub fn sessions(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.sessions.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Drops the responder for session `id`. Returns `false` if it wasn't active.
+    pub fn end_session(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+
+    fn session(&self, id: &str) -> Result<Session, Box<dyn Error>> {
+        if let Some(session) = self.sessions.lock().unwrap().get(id) {
+            return Ok(session.clone());
+        }
+
+        // Build outside the lock so a slow factory doesn't hold up other sessions.
+        // If two threads start the same session, the first one inserted wins.
+        let responder = Arc::new(Mutex::new((self.factory)(id)?));
+        let mut sessions = self.sessions.lock().unwrap();
+        Ok(sessions.entry(id.to_string()).or_insert(responder).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::TextAdapter;
+    use std::thread;
+
+    fn counting_manager() -> SessionManager {
+        SessionManager::new(|id| {
+            let id = id.to_string();
+            let mut count = 0;
+            Ok(Box::new(TextAdapter(move |input: &str| {
+                count += 1;
+                Ok(format!("{} #{}: {}", id, count, input))
+            })))
+        })
+    }
+
+    #[test]
+    fn test_sessions_keep_separate_state() {
+        let manager = counting_manager();
+
+        assert_eq!(manager.respond("a", "hi").unwrap().text, "a #1: hi");
+        assert_eq!(manager.respond("b", "hi").unwrap().text, "b #1: hi");
+        assert_eq!(manager.respond("a", "again").unwrap().text, "a #2: again");
+        assert_eq!(manager.sessions(), vec!["a", "b"]);
+
+        assert!(manager.end_session("a"));
+        assert!(!manager.end_session("a"));
+        assert_eq!(manager.respond("a", "back").unwrap().text, "a #1: back");
+    }
+
+    #[test]
+    fn test_concurrent_sessions() {
+        let manager = Arc::new(counting_manager());
+
+        let handles: Vec<_> = (0..4)
+            .map(|n| {
+                let manager = manager.clone();
+                thread::spawn(move || {
+                    let id = format!("user{}", n);
+                    for _ in 0..10 {
+                        manager.respond(&id, "ping").unwrap();
+                    }
+                    manager.respond(&id, "done").unwrap().text
+                })
+            })
+            .collect();
+
+        for (n, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), format!("user{} #11: done", n));
+        }
+        assert_eq!(manager.sessions().len(), 4);
+    }
+}
@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::chatbot::ChatbotState;
+use crate::config::config_dir;
+
+/// Saves and loads chatbot state by session id.
+pub trait SessionStore: Send + Sync {
+    fn save(&self, id: &str, state: &ChatbotState) -> Result<(), Box<dyn Error>>;
+
+    /// Returns `None` if no session with `id` exists.
+    fn load(&self, id: &str) -> Result<Option<ChatbotState>, Box<dyn Error>>;
+
+    fn list(&self) -> Result<Vec<String>, Box<dyn Error>>;
+
+    fn delete(&self, id: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// Stores each session as `<id>.json` in a directory.
+pub struct JsonFileStore {
+    dir: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// A store in `~/.config/assistant/sessions`, falling back to `./sessions`.
+    pub fn default_location() -> Self {
+        let dir = config_dir()
+            .map(|dir| dir.join("sessions"))
+            .unwrap_or_else(|| PathBuf::from("sessions"));
+
+        Self::new(dir)
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, Box<dyn Error>> {
+        validate_session_id(id)?;
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+/// Session ids name files, so they may only use letters, digits, `-` and `_`.
+pub fn validate_session_id(id: &str) -> Result<(), Box<dyn Error>> {
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "Invalid session id '{}': use letters, digits, '-' and '_' only",
+            id
+        )
+        .into()),
+    }
+}
+
+impl SessionStore for JsonFileStore {
+    fn save(&self, id: &str, state: &ChatbotState) -> Result<(), Box<dyn Error>> {
+        let path = self.path(id)?;
+        std::fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first so a crash never leaves a half-written session.
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(state)?)?;
+        std::fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    fn load(&self, id: &str) -> Result<Option<ChatbotState>, Box<dyn Error>> {
+        let path = self.path(id)?;
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut ids = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn delete(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        match std::fs::remove_file(self.path(id)?) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(format!("No session named '{}'", id).into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Turn;
+
+    fn temp_store(name: &str) -> JsonFileStore {
+        let dir = std::env::temp_dir().join(format!("assistant-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        JsonFileStore::new(dir)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let store = temp_store("session-round-trip");
+        let state = ChatbotState {
+            conversation: vec![Turn::user("Hello"), Turn::assistant("Ahoy")],
+            prefix: Some("Be a pirate.\n".to_string()),
+            ..ChatbotState::default()
+        };
+
+        assert!(store.load("pirate").unwrap().is_none());
+
+        store.save("pirate", &state).unwrap();
+        store.save("other", &ChatbotState::default()).unwrap();
+        assert_eq!(store.load("pirate").unwrap(), Some(state));
+        assert_eq!(store.list().unwrap(), vec!["other", "pirate"]);
+
+        store.delete("pirate").unwrap();
+        assert_eq!(store.list().unwrap(), vec!["other"]);
+        assert!(store.delete("pirate").is_err());
+
+        std::fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_path_traversal() {
+        let store = temp_store("session-invalid");
+        assert!(store.save("../escape", &ChatbotState::default()).is_err());
+        assert!(store.load("").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::code::extract::code_blocks;
+use crate::model_traits::CompletionModel;
+
+pub const DEFAULT_MAX_RETRIES: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "JSON",
+            OutputFormat::Yaml => "YAML",
+        }
+    }
+
+    fn fence_tag(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+        }
+    }
+}
+
+/// One unsuccessful attempt at getting parseable output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseAttempt {
+    pub output: String,
+    pub error: String,
+}
+
+/// Returned when the model never produced output that parsed, with every attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredOutputError {
+    pub format: OutputFormat,
+    pub attempts: Vec<ParseAttempt>,
+}
+
+impl Display for StructuredOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not parse the model's output as {} after {} attempt(s)",
+            self.format.name(),
+            self.attempts.len()
+        )?;
+        if let Some(last) = self.attempts.last() {
+            write!(
+                f,
+                ". Last error: {}\nLast output:\n{}",
+                last.error, last.output
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for StructuredOutputError {}
+
+/// Asks a model for output matching a serde type and parses the reply, re-asking
+/// with the parse error when the reply doesn't parse.
+///
+/// Replies are parsed from a fenced code block if there is one, otherwise from the
+/// whole reply. JSON embedded in surrounding prose is also found.
+#[derive(Debug, Clone, Default)]
+pub struct StructuredOutput {
+    format: OutputFormat,
+    max_retries: Option<usize>,
+    example: Option<serde_json::Value>,
+}
+
+impl StructuredOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn json() -> Self {
+        Self::new().with_format(OutputFormat::Json)
+    }
+
+    pub fn yaml() -> Self {
+        Self::new().with_format(OutputFormat::Yaml)
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// How many times to re-ask after a reply fails to parse. Defaults to
+    /// `DEFAULT_MAX_RETRIES`.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// An example value shown to the model so it knows the expected shape.
+    pub fn with_example<T: Serialize>(mut self, example: &T) -> Result<Self, Box<dyn Error>> {
+        self.example = Some(serde_json::to_value(example)?);
+        Ok(self)
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// The instruction appended to a request, describing the expected output.
+    pub fn instructions(&self) -> String {
+        let mut instructions = format!(
+            "Reply with only {} and no other text.",
+            match self.format {
+                OutputFormat::Json => "a JSON value",
+                OutputFormat::Yaml => "a YAML document",
+            }
+        );
+
+        if let Some(example) = &self.example {
+            let example = match self.format {
+                OutputFormat::Json => serde_json::to_string_pretty(example).unwrap_or_default(),
+                OutputFormat::Yaml => serde_yaml::to_string(example).unwrap_or_default(),
+            };
+            instructions.push_str(&format!(" Use this shape:\n{}", example.trim_end()));
+        }
+
+        instructions
+    }
+
+    /// Parses a model reply as `T`.
+    pub fn parse<T: DeserializeOwned>(&self, output: &str) -> Result<T, String> {
+        // Prefer a block tagged with the format, then any fenced block.
+        let blocks = code_blocks(output);
+        let body = blocks
+            .iter()
+            .find(|block| block.language.as_deref() == Some(self.format.fence_tag()))
+            .or(blocks.first())
+            .map_or(output, |block| block.code.as_str());
+
+        let error = match self.parse_body(body) {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        // Fall back to the outermost JSON object or array inside surrounding prose.
+        if self.format == OutputFormat::Json {
+            if let Some(value) = json_span(body).and_then(|span| serde_json::from_str(span).ok()) {
+                return Ok(value);
+            }
+        }
+
+        Err(error)
+    }
+
+    fn parse_body<T: DeserializeOwned>(&self, body: &str) -> Result<T, String> {
+        match self.format {
+            OutputFormat::Json => serde_json::from_str(body.trim()).map_err(|err| err.to_string()),
+            OutputFormat::Yaml => serde_yaml::from_str(body).map_err(|err| err.to_string()),
+        }
+    }
+
+    /// The follow-up request sent after a reply fails to parse.
+    pub fn correction(&self, error: &str) -> String {
+        format!(
+            "That reply could not be parsed as {}: {}\nTry again. {}",
+            self.format.name(),
+            error,
+            self.instructions()
+        )
+    }
+
+    /// Sends `request` through `ask`, then a correction for each reply that fails to
+    /// parse, until one parses or the retries run out.
+    pub fn ask<T, F>(&self, request: &str, mut ask: F) -> Result<T, Box<dyn Error>>
+    where
+        T: DeserializeOwned,
+        F: FnMut(&str) -> Result<String, Box<dyn Error>>,
+    {
+        let max_retries = self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let mut attempts = Vec::new();
+        let mut request = format!("{}\n{}", request.trim_end(), self.instructions());
+
+        loop {
+            let output = ask(&request)?;
+            let error = match self.parse(&output) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            request = self.correction(&error);
+            attempts.push(ParseAttempt { output, error });
+
+            if attempts.len() > max_retries {
+                return Err(Box::new(StructuredOutputError {
+                    format: self.format,
+                    attempts,
+                }));
+            }
+        }
+    }
+
+    /// Completes `prompt` and parses the completion as `T`. Re-asks continue the same
+    /// prompt, so the model sees its earlier replies and why they were rejected.
+    pub fn complete<T, M>(&self, model: &M, prompt: &str) -> Result<T, Box<dyn Error>>
+    where
+        T: DeserializeOwned,
+        M: CompletionModel + ?Sized,
+    {
+        let mut transcript = String::new();
+
+        self.ask(prompt, |request| {
+            transcript.push_str(request);
+            transcript.push('\n');
+
+            let output = model.complete(&transcript)?;
+            transcript.push_str(output.trim());
+            transcript.push_str("\n\n");
+
+            Ok(output)
+        })
+    }
+}
+
+fn json_span(text: &str) -> Option<&str> {
+    let start = text.find(['{', '['])?;
+    let close = match &text[start..start + 1] {
+        "{" => '}',
+        _ => ']',
+    };
+    let end = text.rfind(close)?;
+
+    (end > start).then(|| &text[start..=end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::Mutex;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Weather {
+        city: String,
+        temperature: i32,
+    }
+
+    fn paris() -> Weather {
+        Weather {
+            city: "Paris".to_string(),
+            temperature: 21,
+        }
+    }
+
+    /// Replies with each of `replies` in turn and records the prompts it was sent.
+    struct ScriptedModel {
+        replies: Mutex<Vec<String>>,
+        prompts: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedModel {
+        fn new(replies: &[&str]) -> Self {
+            Self {
+                replies: Mutex::new(replies.iter().rev().map(|r| r.to_string()).collect()),
+                prompts: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CompletionModel for ScriptedModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            self.prompts.lock().unwrap().push(prompt.to_string());
+            Ok(self.replies.lock().unwrap().pop().unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let json = StructuredOutput::json();
+        let yaml = StructuredOutput::yaml();
+
+        let plain = r#"{"city": "Paris", "temperature": 21}"#;
+        let fenced =
+            "Here you go:\n```json\n{\"city\": \"Paris\", \"temperature\": 21}\n```\nEnjoy!";
+        let prose = r#"The weather is {"city": "Paris", "temperature": 21}, nice."#;
+        let yaml_fenced = "```yaml\ncity: Paris\ntemperature: 21\n```";
+
+        assert_eq!(json.parse::<Weather>(plain).unwrap(), paris());
+        assert_eq!(json.parse::<Weather>(fenced).unwrap(), paris());
+        assert_eq!(json.parse::<Weather>(prose).unwrap(), paris());
+        assert_eq!(yaml.parse::<Weather>(yaml_fenced).unwrap(), paris());
+        assert_eq!(yaml.parse::<Weather>(plain).unwrap(), paris());
+
+        assert!(json.parse::<Weather>(r#"{"city": "Paris"}"#).is_err());
+    }
+
+    #[test]
+    fn test_reask_with_error() {
+        let model = ScriptedModel::new(&[
+            "It's sunny in Paris!",
+            r#"{"city": "Paris", "temperature": 21}"#,
+        ]);
+
+        let weather: Weather = StructuredOutput::json()
+            .complete(&model, "What's the weather in Paris?")
+            .unwrap();
+        assert_eq!(weather, paris());
+
+        let prompts = model.prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[0].ends_with("Reply with only a JSON value and no other text.\n"));
+        assert!(prompts[1].starts_with(&prompts[0]));
+        assert!(
+            prompts[1].contains("It's sunny in Paris!\n\nThat reply could not be parsed as JSON")
+        );
+    }
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let model = ScriptedModel::new(&["nope", "still no", "never"]);
+
+        let output = StructuredOutput::yaml()
+            .with_max_retries(1)
+            .with_example(&paris())
+            .unwrap();
+        let err = model
+            .complete_as::<Weather>("Weather?", &output)
+            .unwrap_err();
+
+        let err = err.downcast_ref::<StructuredOutputError>().unwrap();
+        assert_eq!(err.attempts.len(), 2);
+        assert_eq!(err.attempts[1].output, "still no");
+        assert!(model.prompts.lock().unwrap()[0]
+            .contains("Use this shape:\ncity: Paris\ntemperature: 21"));
+    }
+}
@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::model_traits::{CompletionModel, Responder};
+use crate::response::{keys, Response};
+
+const STYLE_PROMPT: &str = "Rewrite the user's request about how the assistant should write as a single instruction addressed to the assistant, in the second person. Keep it to one sentence. If the request asks the assistant to drop its styles and write normally again, answer RESET instead.
+
+Request: Write like a pirate
+Instruction: Respond as if you are a pirate, with plenty of pirate slang.
+
+Request: Be more formal
+Instruction: Respond in a formal, professional tone.
+
+Request: Okay, you can stop talking like that now
+Instruction: RESET
+
+Request: {request}
+Instruction:";
+
+/// What the style prompt answers for a request to drop every style.
+const RESET: &str = "RESET";
+
+/// Style instructions shared between a `StyleManager` and the chatbots it styles.
+///
+/// Styles stack in the order they were added. Resetting removes all of them,
+/// including any the handle started with, leaving the chatbot's base persona.
+#[derive(Clone, Default)]
+pub struct StyleHandle {
+    styles: Arc<Mutex<Vec<String>>>,
+}
+
+impl StyleHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A handle that starts with `styles` active.
+    pub fn with_styles(styles: Vec<String>) -> Self {
+        Self {
+            styles: Arc::new(Mutex::new(styles)),
+        }
+    }
+
+    pub fn styles(&self) -> Vec<String> {
+        self.styles.lock().unwrap().clone()
+    }
+
+    pub fn push(&self, instruction: &str) {
+        self.styles.lock().unwrap().push(instruction.to_string());
+    }
+
+    /// Replaces every active style with `styles`.
+    pub fn set(&self, styles: Vec<String>) {
+        *self.styles.lock().unwrap() = styles;
+    }
+
+    pub fn reset(&self) {
+        self.set(Vec::new());
+    }
+
+    /// The active styles as instructions, one per line.
+    pub fn instructions(&self) -> String {
+        self.styles().join("\n")
+    }
+}
+
+/// What a style request asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StyleChange {
+    /// Adds an instruction to the active styles.
+    Add(String),
+    /// Removes every active style.
+    Reset,
+}
+
+/// Handles `style_change` requests by turning them into persistent instructions
+/// on a `StyleHandle` shared with the target chatbot.
+pub struct StyleManager<M: CompletionModel> {
+    model: M,
+    handle: StyleHandle,
+}
+
+impl<M: CompletionModel> StyleManager<M> {
+    pub fn new(model: M, handle: StyleHandle) -> Self {
+        Self { model, handle }
+    }
+
+    pub fn handle(&self) -> &StyleHandle {
+        &self.handle
+    }
+
+    /// Asks the model whether `request` resets the styles, or else rewrites a request
+    /// like "Write like a pirate" as an instruction to the assistant.
+    pub fn interpret(&self, request: &str) -> Result<StyleChange, Box<dyn Error>> {
+        let prompt = STYLE_PROMPT.replace("{request}", request.trim());
+        let instruction = self.model.complete(&prompt)?.trim().to_string();
+
+        if instruction.is_empty() {
+            Ok(StyleChange::Add(request.trim().to_string()))
+        } else if instruction.trim_end_matches('.') == RESET {
+            Ok(StyleChange::Reset)
+        } else {
+            Ok(StyleChange::Add(instruction))
+        }
+    }
+}
+
+impl<M: CompletionModel> Responder for StyleManager<M> {
+    fn respond(&mut self, input: &str) -> Result<Response, Box<dyn Error>> {
+        let text = match self.interpret(input)? {
+            StyleChange::Reset => {
+                self.handle.reset();
+                "Okay, I'm back to my usual style.".to_string()
+            }
+            StyleChange::Add(instruction) => {
+                self.handle.push(&instruction);
+                format!("Got it! From now on: {}", instruction)
+            }
+        };
+
+        Ok(Response::new(&text).with_metadata(keys::STYLES, self.handle.styles()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::MetadataValue;
+
+    struct EchoRequestModel;
+
+    impl CompletionModel for EchoRequestModel {
+        fn complete(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+            let request = prompt
+                .rsplit("Request: ")
+                .next()
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap();
+            match request.contains("normal") {
+                true => Ok(" RESET".to_string()),
+                false => Ok(format!(" Respond like this: {}.", request.to_lowercase())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_stack_and_reset_styles() {
+        let handle = StyleHandle::with_styles(vec!["Be a pirate.".to_string()]);
+        let mut manager = StyleManager::new(EchoRequestModel, handle.clone());
+
+        let confirmation = manager.respond("Be more formal").unwrap().text;
+        assert_eq!(
+            confirmation,
+            "Got it! From now on: Respond like this: be more formal."
+        );
+
+        manager.respond("Respond in haiku").unwrap();
+        assert_eq!(
+            handle.instructions(),
+            "Be a pirate.\nRespond like this: be more formal.\nRespond like this: respond in haiku."
+        );
+
+        let response = manager.respond("Please go back to normal").unwrap();
+        assert_eq!(handle.instructions(), "");
+        assert_eq!(
+            response.metadata(keys::STYLES),
+            Some(&MetadataValue::List(Vec::new()))
+        );
+
+        manager.respond("Be more formal").unwrap();
+        assert_eq!(handle.styles(), vec!["Respond like this: be more formal."]);
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+/// A prompt template.
+///
+/// Syntax:
+/// - `{name}` inserts the variable `name`. Rendering fails if it isn't set.
+/// - `{#if name}...{#else}...{/if}` renders the first branch if `name` is set and
+///   not empty, and the optional `{#else}` branch otherwise.
+/// - `{>name}` includes the template registered as `name`.
+/// - `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    name: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Variable(String),
+    Include(String),
+    If {
+        variable: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+enum Tag {
+    Variable(String),
+    Include(String),
+    If(String),
+    Else,
+    EndIf,
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn parse_tag(tag: &str) -> Result<Tag, Box<dyn Error>> {
+    let tag = tag.trim();
+
+    let parsed = if let Some(name) = tag.strip_prefix("#if ") {
+        Tag::If(name.trim().to_string())
+    } else if tag == "#else" {
+        Tag::Else
+    } else if tag == "/if" {
+        Tag::EndIf
+    } else if let Some(name) = tag.strip_prefix('>') {
+        Tag::Include(name.trim().to_string())
+    } else {
+        Tag::Variable(tag.to_string())
+    };
+
+    match &parsed {
+        Tag::Variable(name) | Tag::Include(name) | Tag::If(name) if !is_identifier(name) => {
+            Err(format!("Invalid template tag '{{{}}}'", tag).into())
+        }
+        _ => Ok(parsed),
+    }
+}
+
+struct Frame {
+    variable: String,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl PromptTemplate {
+    pub fn parse(name: &str, source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut root: Vec<Node> = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        fn current<'a>(root: &'a mut Vec<Node>, stack: &'a mut [Frame]) -> &'a mut Vec<Node> {
+            match stack.last_mut() {
+                Some(Frame {
+                    otherwise: Some(otherwise),
+                    ..
+                }) => otherwise,
+                Some(frame) => &mut frame.then,
+                None => root,
+            }
+        }
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => {
+                                return Err(format!(
+                                    "Unclosed tag '{{{}' in template '{}'",
+                                    tag, name
+                                )
+                                .into())
+                            }
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        current(&mut root, &mut stack).push(Node::Text(std::mem::take(&mut text)));
+                    }
+
+                    match parse_tag(&tag)
+                        .map_err(|err| format!("{} in template '{}'", err, name))?
+                    {
+                        Tag::Variable(variable) => {
+                            current(&mut root, &mut stack).push(Node::Variable(variable))
+                        }
+                        Tag::Include(include) => {
+                            current(&mut root, &mut stack).push(Node::Include(include))
+                        }
+                        Tag::If(variable) => stack.push(Frame {
+                            variable,
+                            then: Vec::new(),
+                            otherwise: None,
+                        }),
+                        Tag::Else => match stack.last_mut() {
+                            Some(frame) if frame.otherwise.is_none() => {
+                                frame.otherwise = Some(Vec::new())
+                            }
+                            _ => {
+                                return Err(
+                                    format!("Unexpected {{#else}} in template '{}'", name).into()
+                                )
+                            }
+                        },
+                        Tag::EndIf => {
+                            let frame = stack.pop().ok_or_else(|| {
+                                format!("Unexpected {{/if}} in template '{}'", name)
+                            })?;
+                            current(&mut root, &mut stack).push(Node::If {
+                                variable: frame.variable,
+                                then: frame.then,
+                                otherwise: frame.otherwise.unwrap_or_default(),
+                            });
+                        }
+                    }
+                }
+                '}' => return Err(format!("Unmatched '}}' in template '{}'", name).into()),
+                c => text.push(c),
+            }
+        }
+
+        if let Some(frame) = stack.last() {
+            return Err(format!(
+                "Missing {{/if}} for {{#if {}}} in template '{}'",
+                frame.variable, name
+            )
+            .into());
+        }
+
+        if !text.is_empty() {
+            root.push(Node::Text(text));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            nodes: root,
+        })
+    }
+
+    /// Loads a template from a file, named after the file's stem.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Invalid template path {}", path.display()))?;
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read template {}: {}", path.display(), err))?;
+
+        Self::parse(name, &source)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Variables that must be set to render this template, including those of
+    /// included templates. Variables only used inside an `{#if}` on themselves are
+    /// optional.
+    pub fn required_variables(
+        &self,
+        registry: &TemplateRegistry,
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        let mut required = HashSet::new();
+        let mut visiting = vec![self.name.clone()];
+        collect_required(
+            &self.nodes,
+            registry,
+            &mut Vec::new(),
+            &mut visiting,
+            &mut required,
+        )?;
+        Ok(required)
+    }
+
+    /// Checks that every required variable is in `variables` and every include exists.
+    pub fn validate(
+        &self,
+        registry: &TemplateRegistry,
+        variables: &HashSet<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut missing: Vec<String> = self
+            .required_variables(registry)?
+            .difference(variables)
+            .cloned()
+            .collect();
+        missing.sort();
+
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "Template '{}' uses undefined variables: {}",
+                self.name,
+                missing.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    pub fn render(
+        &self,
+        variables: &HashMap<String, String>,
+        registry: &TemplateRegistry,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut output = String::new();
+        let mut visiting = vec![self.name.clone()];
+        render_nodes(&self.nodes, variables, registry, &mut visiting, &mut output)?;
+        Ok(output)
+    }
+}
+
+fn collect_required(
+    nodes: &[Node],
+    registry: &TemplateRegistry,
+    guards: &mut Vec<String>,
+    visiting: &mut Vec<String>,
+    required: &mut HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Variable(variable) => {
+                if !guards.contains(variable) {
+                    required.insert(variable.clone());
+                }
+            }
+            Node::Include(name) => {
+                let template = registry.include(name, visiting)?;
+                visiting.push(name.clone());
+                collect_required(&template.nodes, registry, guards, visiting, required)?;
+                visiting.pop();
+            }
+            Node::If {
+                variable,
+                then,
+                otherwise,
+            } => {
+                guards.push(variable.clone());
+                collect_required(then, registry, guards, visiting, required)?;
+                guards.pop();
+                collect_required(otherwise, registry, guards, visiting, required)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    variables: &HashMap<String, String>,
+    registry: &TemplateRegistry,
+    visiting: &mut Vec<String>,
+    output: &mut String,
+) -> Result<(), Box<dyn Error>> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(variable) => match variables.get(variable) {
+                Some(value) => output.push_str(value),
+                None => return Err(format!("Template variable '{}' is not set", variable).into()),
+            },
+            Node::Include(name) => {
+                let template = registry.include(name, visiting)?;
+                visiting.push(name.clone());
+                render_nodes(&template.nodes, variables, registry, visiting, output)?;
+                visiting.pop();
+            }
+            Node::If {
+                variable,
+                then,
+                otherwise,
+            } => {
+                let set = variables
+                    .get(variable)
+                    .is_some_and(|value| !value.is_empty());
+                let branch = if set { then } else { otherwise };
+                render_nodes(branch, variables, registry, visiting, output)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Named templates available to `{>name}` includes.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateRegistry {
+    templates: HashMap<String, PromptTemplate>,
+}
+
+impl TemplateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, template: PromptTemplate) {
+        self.templates.insert(template.name.clone(), template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PromptTemplate> {
+        self.templates.get(name)
+    }
+
+    /// Loads every `.txt` and `.tmpl` file in `dir`, named after its file stem.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        let mut registry = Self::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            if matches!(extension, Some("txt") | Some("tmpl")) {
+                registry.add(PromptTemplate::from_file(&path)?);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    fn include(&self, name: &str, visiting: &[String]) -> Result<&PromptTemplate, Box<dyn Error>> {
+        if visiting.iter().any(|visited| visited == name) {
+            return Err(format!("Template '{}' includes itself", name).into());
+        }
+
+        self.get(name)
+            .ok_or_else(|| format!("Included template '{}' not found", name).into())
+    }
+}
+
+/// Today's date in UTC, formatted as `YYYY-MM-DD`.
+pub fn current_date() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let template = PromptTemplate::parse(
+            "test",
+            "Hi {name}.{#if mood} You seem {mood}.{#else} How are you?{/if} {{literal}}",
+        )
+        .unwrap();
+        let registry = TemplateRegistry::new();
+
+        assert_eq!(
+            template
+                .render(&variables(&[("name", "Bob"), ("mood", "happy")]), &registry)
+                .unwrap(),
+            "Hi Bob. You seem happy. {literal}"
+        );
+        assert_eq!(
+            template
+                .render(&variables(&[("name", "Bob")]), &registry)
+                .unwrap(),
+            "Hi Bob. How are you? {literal}"
+        );
+        assert!(template.render(&variables(&[]), &registry).is_err());
+    }
+
+    #[test]
+    fn test_includes_and_validation() {
+        let mut registry = TemplateRegistry::new();
+        registry.add(PromptTemplate::parse("persona", "You are {persona}.").unwrap());
+        let template =
+            PromptTemplate::parse("main", "{>persona}\n{#if extra}{extra}{/if}{input}").unwrap();
+
+        let required = template.required_variables(&registry).unwrap();
+        assert_eq!(
+            required,
+            ["persona", "input"].iter().map(|s| s.to_string()).collect()
+        );
+
+        let defined = ["input".to_string()].into_iter().collect();
+        let err = template.validate(&registry, &defined).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Template 'main' uses undefined variables: persona"
+        );
+
+        assert_eq!(
+            template
+                .render(
+                    &variables(&[("persona", "a pirate"), ("input", "Hi")]),
+                    &registry
+                )
+                .unwrap(),
+            "You are a pirate.\nHi"
+        );
+    }
+
+    #[test]
+    fn test_recursive_include() {
+        let mut registry = TemplateRegistry::new();
+        registry.add(PromptTemplate::parse("loop", "{>loop}").unwrap());
+
+        let template = registry.get("loop").unwrap();
+        assert!(template.render(&HashMap::new(), &registry).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PromptTemplate::parse("t", "{#if a}unclosed").is_err());
+        assert!(PromptTemplate::parse("t", "{/if}").is_err());
+        assert!(PromptTemplate::parse("t", "{open").is_err());
+        assert!(PromptTemplate::parse("t", "close}").is_err());
+        assert!(PromptTemplate::parse("t", "{bad name}").is_err());
+    }
+
+    #[test]
+    fn test_current_date_format() {
+        let date = current_date();
+        assert_eq!(date.len(), 10);
+        assert!(date.starts_with("20"));
+    }
+}
@@ -0,0 +1,64 @@
+/// Estimates how many tokens a model will see for a piece of text.
+pub trait TokenCounter: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+/// A tokenizer-free estimate: roughly four characters per token, and never
+/// fewer tokens than words.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproxTokenCounter;
+
+impl TokenCounter for ApproxTokenCounter {
+    fn count(&self, text: &str) -> usize {
+        let chars = text.chars().count();
+        let words = text.split_whitespace().count();
+        std::cmp::max(chars.div_ceil(4), words)
+    }
+}
+
+/// Truncates `text` to approximately `max_tokens` tokens, marking the cut with an ellipsis.
+pub fn truncate_to_tokens<C: TokenCounter + ?Sized>(
+    counter: &C,
+    text: &str,
+    max_tokens: usize,
+) -> String {
+    if counter.count(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        if counter.count(&truncated) + counter.count(word) + 1 > max_tokens {
+            break;
+        }
+        truncated.push_str(word);
+    }
+
+    format!("{}...", truncated.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approx_count() {
+        let counter = ApproxTokenCounter;
+
+        assert_eq!(counter.count(""), 0);
+        assert_eq!(counter.count("a b c d e"), 5);
+        assert_eq!(counter.count("abcdefgh"), 2);
+    }
+
+    #[test]
+    fn test_truncate_to_tokens() {
+        let counter = ApproxTokenCounter;
+        let text = "one two three four five six seven eight";
+
+        assert_eq!(truncate_to_tokens(&counter, text, 100), text);
+
+        let truncated = truncate_to_tokens(&counter, text, 4);
+        assert_eq!(truncated, "one two...");
+        assert!(counter.count(&truncated) <= 4);
+    }
+}